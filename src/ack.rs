@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+// How many events `AckPolicy::All` lets go unacked while a `Channel` keeps
+// reading events
+const ALL_MAX_EVENTS: u32 = 50;
+
+// How long `AckPolicy::All` lets an event go unacked while a `Channel` keeps
+// reading events, well within the window after which eyre considers a
+// channel clogged
+const ALL_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// How a `Channel` acks the SSE events it receives. Eyre acks are
+/// cumulative, so acking an event also acks every event before it. Events
+/// which are never acked pile up on the ship until it kills the channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum AckPolicy {
+    /// Ack every event, with the acks of events parsed together sent in
+    /// one request. While events keep arriving, they are acked every 50
+    /// events or every second.
+    #[default]
+    All,
+    /// Ack the highest event id once `events` events are unacked, or once
+    /// the oldest unacked event has waited `interval`
+    Every { events: u32, interval: Duration },
+    /// Never ack automatically. Events are acked with `Channel::ack`.
+    Manual,
+}
+
+// Tracks the events which have been received but not yet acked
+#[derive(Debug, Default)]
+pub(crate) struct AckTracker {
+    /// The number of unacked events
+    unacked: u32,
+    /// The highest unacked event id
+    highest: Option<u64>,
+    /// When the oldest unacked event was received
+    since: Option<Instant>,
+}
+
+impl AckTracker {
+    /// Records that the event with the given id was received
+    pub(crate) fn record(&mut self, event_id: u64) {
+        if self.since.is_none() {
+            self.since = Some(Instant::now());
+        }
+        self.unacked = self.unacked.saturating_add(1);
+        self.highest = std::cmp::max(self.highest, Some(event_id));
+    }
+
+    /// Records that the ack of the event with the given id failed, so that
+    /// it is acked again
+    pub(crate) fn retry(&mut self, event_id: u64) {
+        self.record(event_id);
+    }
+
+    /// Records that the event with the given id, and every event before
+    /// it, has been acked. The unacked events are only forgotten once none
+    /// of them is after it.
+    pub(crate) fn acked(&mut self, event_id: u64) {
+        if self.highest.is_some_and(|highest| highest <= event_id) {
+            self.unacked = 0;
+            self.since = None;
+            self.highest = None;
+        }
+    }
+
+    /// How long until the oldest unacked event has waited long enough for
+    /// `policy` to ack it while events are being read, if there are
+    /// unacked events
+    pub(crate) fn due_in(&self, policy: &AckPolicy) -> Option<Duration> {
+        let interval = match policy {
+            AckPolicy::All => ALL_MAX_DELAY,
+            AckPolicy::Every { interval, .. } => *interval,
+            AckPolicy::Manual => return None,
+        };
+        self.since
+            .map(|since| interval.saturating_sub(since.elapsed()))
+    }
+
+    /// Whether `policy` says the unacked events are due to be acked while
+    /// events are still being read
+    pub(crate) fn is_due(&self, policy: &AckPolicy) -> bool {
+        let (events, interval) = match policy {
+            AckPolicy::All => (ALL_MAX_EVENTS, ALL_MAX_DELAY),
+            AckPolicy::Every { events, interval } => (*events, *interval),
+            AckPolicy::Manual => return false,
+        };
+        self.unacked >= events || self.since.is_some_and(|since| since.elapsed() >= interval)
+    }
+
+    /// Returns the highest unacked event id if `policy` says it is due to
+    /// be acked, and forgets the unacked events. The events of
+    /// `AckPolicy::All` are always due once reading stops.
+    pub(crate) fn take_due(&mut self, policy: &AckPolicy) -> Option<u64> {
+        if *policy != AckPolicy::All && !self.is_due(policy) {
+            return None;
+        }
+        self.unacked = 0;
+        self.since = None;
+        self.highest.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that only the highest event id is acked every `events` events
+    fn acks_every_n_events() {
+        let policy = AckPolicy::Every {
+            events: 3,
+            interval: Duration::from_secs(60),
+        };
+        let mut tracker = AckTracker::default();
+        tracker.record(1);
+        tracker.record(2);
+        assert_eq!(tracker.take_due(&policy), None);
+        tracker.record(3);
+        assert_eq!(tracker.take_due(&policy), Some(3));
+        assert_eq!(tracker.take_due(&policy), None);
+        tracker.record(4);
+        assert_eq!(tracker.take_due(&AckPolicy::Manual), None);
+        assert_eq!(tracker.take_due(&AckPolicy::All), Some(4));
+    }
+
+    #[test]
+    // Verify that `AckPolicy::All` acks every `ALL_MAX_EVENTS` events while
+    // events keep arriving
+    fn acks_all_while_reading() {
+        let mut tracker = AckTracker::default();
+        assert_eq!(tracker.due_in(&AckPolicy::All), None);
+        for eid in 1..ALL_MAX_EVENTS as u64 {
+            tracker.record(eid);
+        }
+        assert!(!tracker.is_due(&AckPolicy::All));
+        assert!(tracker.due_in(&AckPolicy::All).unwrap() <= ALL_MAX_DELAY);
+        tracker.record(ALL_MAX_EVENTS as u64);
+        assert!(tracker.is_due(&AckPolicy::All));
+        assert!(!tracker.is_due(&AckPolicy::Manual));
+    }
+
+    #[test]
+    // Verify that events acked by hand are forgotten, so that they are not
+    // acked again
+    fn forgets_events_acked_by_hand() {
+        let policy = AckPolicy::Every {
+            events: 2,
+            interval: Duration::from_secs(60),
+        };
+        let mut tracker = AckTracker::default();
+        tracker.record(1);
+        tracker.record(2);
+        tracker.acked(1);
+        assert_eq!(tracker.take_due(&policy), Some(2));
+        tracker.record(3);
+        tracker.record(4);
+        tracker.acked(4);
+        assert_eq!(tracker.take_due(&policy), None);
+        assert_eq!(tracker.due_in(&policy), None);
+    }
+
+    #[test]
+    // Verify that events which are never acked do not overflow the count
+    fn saturates_unacked_count() {
+        let mut tracker = AckTracker {
+            unacked: u32::MAX,
+            ..AckTracker::default()
+        };
+        tracker.record(1);
+        assert_eq!(tracker.unacked, u32::MAX);
+    }
+}
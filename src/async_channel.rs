@@ -0,0 +1,562 @@
+use crate::async_interface::AsyncShipInterface;
+use crate::error::{Result, UrbitAPIError};
+use crate::event::ChannelEvent;
+use crate::options::ChannelOptions;
+use crate::sse::{Event, EventParser};
+pub use crate::subscription::SubscriptionMessage;
+use crate::subscription::{CreationID, Subscription};
+#[cfg(feature = "serde")]
+use crate::typed;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use json::{object, JsonValue};
+use reqwest::Response;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The async counterpart of `Channel`, which is used to interact with a ship
+pub struct AsyncChannel {
+    /// `AsyncShipInterface` this channel is created from
+    pub ship_interface: AsyncShipInterface,
+    /// The uid of the channel
+    pub uid: String,
+    /// The url of the channel
+    pub url: String,
+    /// The list of `Subscription`s for this channel
+    pub subscription_list: Vec<Subscription>,
+    /// The stream of SSE events for this channel
+    event_stream: BoxStream<'static, Result<Event>>,
+    /// The current number of messages that have been sent out (which are
+    /// also defined as message ids) via this `AsyncChannel`
+    pub message_id_count: u64,
+    /// How long to wait for the ship to ack a new subscription
+    pub ack_timeout: Duration,
+    /// The messages which were read while waiting for an ack, and have not
+    /// been returned by `next_message` yet
+    pending_messages: VecDeque<SubscriptionMessage>,
+}
+
+impl AsyncChannel {
+    /// Create a new channel
+    pub async fn new(ship_interface: AsyncShipInterface) -> Result<AsyncChannel> {
+        AsyncChannel::with_options(ship_interface, ChannelOptions::default()).await
+    }
+
+    /// Create a new channel with the given `ChannelOptions`. If the channel
+    /// is opened with `OpenAction::Subscribe`, waits up to `ack_timeout`
+    /// for the app to ack the subscription like `create_new_subscription`,
+    /// and deletes the channel if it is not acked.
+    pub async fn with_options(
+        ship_interface: AsyncShipInterface,
+        options: ChannelOptions,
+    ) -> Result<AsyncChannel> {
+        let uid = options.new_uid();
+
+        // Channel url
+        let channel_url = format!("{}/~/channel/{}", &ship_interface.url, uid);
+        // Opening channel request json
+        let mut body = json::parse(r#"[]"#).unwrap();
+        body[0] = options.open_action.to_json(&ship_interface.ship_name);
+        // The `Subscription` created by the opening action, if any
+        let subscription = options
+            .open_action
+            .subscription(&uid, &ship_interface.ship_name);
+
+        // Make the put request to create the channel.
+        let resp = ship_interface.send_put_request(&channel_url, &body).await?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToCreateNewChannel);
+        }
+
+        // Open the SSE connection of the channel
+        let resp = ship_interface
+            .send_event_stream_request(&channel_url)
+            .await?;
+        if !resp.status().is_success() {
+            return Err(UrbitAPIError::FailedToCreateNewChannel);
+        }
+
+        let mut channel = AsyncChannel {
+            ship_interface,
+            uid,
+            url: channel_url,
+            subscription_list: vec![],
+            event_stream: event_stream(resp),
+            message_id_count: 2,
+            ack_timeout: Duration::from_secs(30),
+            pending_messages: VecDeque::new(),
+        };
+
+        // Wait for the app to accept the opening subscription
+        if let Some(sub) = subscription {
+            let err = match channel.wait_for_ack(sub.creation_id).await {
+                Ok(Ok(())) => {
+                    channel.subscription_list.push(sub);
+                    return Ok(channel);
+                }
+                Ok(Err(trace)) => UrbitAPIError::SubscriptionNacked(trace),
+                Err(e) => e,
+            };
+            // Deleting the channel also ends the subscription if it was
+            // accepted after all. The error of the subscription is the one
+            // worth returning.
+            let _ = channel.delete_channel().await;
+            return Err(err);
+        }
+        Ok(channel)
+    }
+
+    /// Acquires and returns the current `message_id_count` while also
+    /// increase said value by 1.
+    pub fn get_and_raise_message_id_count(&mut self) -> u64 {
+        let current_id_count = self.message_id_count;
+        self.message_id_count += 1;
+        current_id_count
+    }
+
+    /// Sends a poke over the channel
+    pub async fn poke(&mut self, app: &str, mark: &str, json: &str) -> Result<Response> {
+        self.send_poke(app, mark, json.into()).await
+    }
+
+    /// Sends a poke over the channel with `data` serialized as the
+    /// structured json of the poke
+    #[cfg(feature = "serde")]
+    pub async fn poke_typed<T: Serialize>(
+        &mut self,
+        app: &str,
+        mark: &str,
+        data: &T,
+    ) -> Result<Response> {
+        let json = typed::to_json_value(data)?;
+        self.send_poke(app, mark, json).await
+    }
+
+    /// Sends a poke with the given json over the channel
+    async fn send_poke(&mut self, app: &str, mark: &str, json: JsonValue) -> Result<Response> {
+        let mut body = json::parse(r#"[]"#).unwrap();
+        body[0] = object! {
+                "id": self.get_and_raise_message_id_count(),
+                "action": "poke",
+                "ship": self.ship_interface.ship_name.clone(),
+                "app": app,
+                "mark": mark,
+                "json": json,
+        };
+
+        // Make the put request for the poke
+        self.ship_interface.send_put_request(&self.url, &body).await
+    }
+
+    /// Create a new `Subscription` and thus subscribes to events on the
+    /// ship with the provided app/path. Like `Channel`, waits up to
+    /// `ack_timeout` for the app to ack the subscription, and only then adds
+    /// it to the `subscription_list`. If the app nacks the subscription, the
+    /// error trace is returned as `UrbitAPIError::SubscriptionNacked`. If no
+    /// ack arrives in time, the ship is told to end the subscription and
+    /// `UrbitAPIError::AckTimeout` is returned. Messages read while waiting
+    /// are kept for `next_message`. It must be awaited inside of a tokio
+    /// runtime with the time driver enabled.
+    pub async fn create_new_subscription(&mut self, app: &str, path: &str) -> Result<CreationID> {
+        // Saves the message id to be reused
+        let creation_id = self.get_and_raise_message_id_count();
+        // Create the json body
+        let mut body = json::parse(r#"[]"#).unwrap();
+        body[0] = object! {
+                "id": creation_id,
+                "action": "subscribe",
+                "ship": self.ship_interface.ship_name.clone(),
+                "app": app.to_string(),
+                "path": path.to_string(),
+        };
+
+        // Make the put request to create the subscription.
+        let resp = self
+            .ship_interface
+            .send_put_request(&self.url, &body)
+            .await?;
+
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToCreateNewSubscription);
+        }
+
+        // Wait for the app to accept the subscription
+        let ack = match self.wait_for_ack(creation_id).await {
+            Err(UrbitAPIError::AckTimeout(id)) => {
+                // The watch may still be accepted once the ack is lost, so
+                // leave it rather than having it send facts nobody reads
+                let _ = self.send_unsubscribe(creation_id).await;
+                return Err(UrbitAPIError::AckTimeout(id));
+            }
+            ack => ack?,
+        };
+        ack.map_err(UrbitAPIError::SubscriptionNacked)?;
+
+        // Create the `Subscription`
+        let sub = Subscription {
+            channel_uid: self.uid.clone(),
+            creation_id,
+            ship: self.ship_interface.ship_name.clone(),
+            app: app.to_string(),
+            path: path.to_string(),
+            message_list: vec![],
+        };
+        // Add the `Subscription` to the list
+        self.subscription_list.push(sub);
+        Ok(creation_id)
+    }
+
+    /// Acks the SSE event with the given id, letting the ship know that
+    /// it does not need to be resent.
+    pub async fn ack(&mut self, event_id: u64) -> Result<Response> {
+        let mut json = json::parse(r#"[]"#).unwrap();
+        json[0] = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "ack",
+            "event-id": event_id,
+        };
+        self.ship_interface.send_put_request(&self.url, &json).await
+    }
+
+    /// Reads SSE events until a message arrives for one of the
+    /// `Subscription`s in the `subscription_list`, and returns it. Every
+    /// event which is read is acked, even ones which match no subscription.
+    /// Returns `None` once the SSE connection closes.
+    pub async fn next_message(&mut self) -> Option<Result<SubscriptionMessage>> {
+        loop {
+            if let Some(message) = self.pending_messages.pop_front() {
+                return Some(Ok(message));
+            }
+            match self.next_event().await? {
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// A `Stream` of the messages for all of the `Subscription`s in the
+    /// `subscription_list`. Every event is acked as it is read.
+    pub fn messages(&mut self) -> impl Stream<Item = Result<SubscriptionMessage>> + '_ {
+        stream::unfold(self, |channel| async move {
+            channel
+                .next_message()
+                .await
+                .map(|message| (message, channel))
+        })
+    }
+
+    /// Reads the next SSE event, queues the message it holds for one of the
+    /// `Subscription`s in the `subscription_list`, and acks it, so that
+    /// events do not pile up on the ship. If the ack fails, the message
+    /// stays queued and the error is returned. Returns `None` once the SSE
+    /// connection closes.
+    async fn next_event(&mut self) -> Option<Result<ChannelEvent>> {
+        let event = match self.event_stream.next().await? {
+            Ok(event) => event,
+            Err(e) => return Some(Err(e)),
+        };
+        let channel_event = ChannelEvent::parse(&event.data);
+        if let Some(message) = self.match_event(&channel_event) {
+            self.pending_messages.push_back(message);
+        }
+        // Eyre numbers every event. An event without an id acks nothing.
+        let eid = event.id.as_ref().and_then(|id| id.parse().ok());
+        if let Some(eid) = eid.filter(|eid| *eid > 0) {
+            match self.ack(eid).await {
+                Ok(resp) if resp.status().as_u16() == 204 => {}
+                Ok(_) => return Some(Err(UrbitAPIError::FailedToAck(eid))),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(channel_event))
+    }
+
+    /// Waits up to `ack_timeout` for the ship to respond to the
+    /// subscription with the given id. Returns the error trace of the app
+    /// if it was nacked.
+    async fn wait_for_ack(&mut self, id: u64) -> Result<std::result::Result<(), String>> {
+        let timeout = self.ack_timeout;
+        let ack = async {
+            loop {
+                match self.next_event().await {
+                    Some(Ok(ChannelEvent::WatchAck { id: acked, result })) if acked == id => {
+                        return Ok(result)
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                    None => return Err(UrbitAPIError::EventStreamClosed),
+                }
+            }
+        };
+        tokio::time::timeout(timeout, ack)
+            .await
+            .map_err(|_| UrbitAPIError::AckTimeout(id))?
+    }
+
+    /// Finds the `Subscription` which an event is for and builds the
+    /// `SubscriptionMessage` out of it.
+    fn match_event(&self, event: &ChannelEvent) -> Option<SubscriptionMessage> {
+        let (id, payload) = match event {
+            ChannelEvent::Fact { id, payload, .. } => (*id, payload),
+            _ => return None,
+        };
+        let sub = self
+            .subscription_list
+            .iter()
+            .find(|s| s.creation_id == id)?;
+        if payload.is_null() {
+            return None;
+        }
+        Some(SubscriptionMessage {
+            creation_id: sub.creation_id,
+            ship: sub.ship.clone(),
+            app: sub.app.clone(),
+            path: sub.path.clone(),
+            message: payload.dump(),
+        })
+    }
+
+    /// Finds the first `Subscription` in the list which has a matching
+    /// `app` and `path` on the ship of this channel
+    pub fn find_subscription(&mut self, app: &str, path: &str) -> Option<&mut Subscription> {
+        let ship = &self.ship_interface.ship_name;
+        self.subscription_list
+            .iter_mut()
+            .find(|s| &s.ship == ship && s.app == app && s.path == path)
+    }
+
+    /// Finds the first `Subscription` in the list which has a matching
+    /// `app` and `path`, tells the ship that you are unsubscribing, and
+    /// removes it from the list. Returns
+    /// `UrbitAPIError::SubscriptionNotFound` if failed to find a
+    /// subscription with a matching app & path.
+    pub async fn unsubscribe(&mut self, app: &str, path: &str) -> Result<()> {
+        let index = self
+            .subscription_list
+            .iter()
+            .position(|s| s.ship == self.ship_interface.ship_name && s.app == app && s.path == path)
+            .ok_or_else(|| {
+                UrbitAPIError::SubscriptionNotFound(app.to_string(), path.to_string())
+            })?;
+
+        self.send_unsubscribe(self.subscription_list[index].creation_id)
+            .await?;
+
+        self.subscription_list.remove(index);
+        Ok(())
+    }
+
+    /// Tells the ship to end the subscription with the given `creation_id`
+    async fn send_unsubscribe(&mut self, creation_id: CreationID) -> Result<()> {
+        let mut json = json::parse(r#"[]"#).unwrap();
+        json[0] = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "unsubscribe",
+            "subscription": creation_id,
+        };
+        let resp = self
+            .ship_interface
+            .send_put_request(&self.url, &json)
+            .await?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToUnsubscribe);
+        }
+        Ok(())
+    }
+
+    /// Deletes the channel
+    pub async fn delete_channel(mut self) -> Result<()> {
+        let mut json = json::parse(r#"[]"#).unwrap();
+        json[0] = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "delete",
+        };
+        let resp = self
+            .ship_interface
+            .send_put_request(&self.url, &json)
+            .await?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToDeleteChannel);
+        }
+        Ok(())
+    }
+}
+
+/// Turns the SSE `Response` of a channel into a stream of `Event`s
+fn event_stream(resp: Response) -> BoxStream<'static, Result<Event>> {
+    let state = (
+        resp.bytes_stream().boxed(),
+        EventParser::new(),
+        VecDeque::new(),
+    );
+    stream::unfold(state, |(mut bytes, mut parser, mut pending)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                return Some((Ok(event), (bytes, parser, pending)));
+            }
+            match bytes.next().await {
+                Some(Ok(chunk)) => pending.extend(parser.feed(&chunk)),
+                Some(Err(e)) => {
+                    return Some((Err(UrbitAPIError::from(e)), (bytes, parser, pending)))
+                }
+                None => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::OpenAction;
+    use crate::test_support::{fake_server, respond, EVENT_STREAM, NO_CONTENT};
+    use std::io::Write;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+
+    // Starts a fake ship which logs in with any code, streams the chunks
+    // sent to the returned `Sender` as the SSE connection of a channel, and
+    // sends the json body of every PUT to the returned `Receiver`
+    fn fake_ship() -> (String, Sender<Vec<u8>>, Receiver<JsonValue>) {
+        let (chunk_sender, chunks) = mpsc::channel::<Vec<u8>>();
+        let chunks = Mutex::new(Some(chunks));
+        let (put_sender, puts) = mpsc::channel();
+        let put_sender = Mutex::new(put_sender);
+        let url = fake_server(move |request, stream| {
+            if request.is("POST", "/~/login") {
+                let resp = "HTTP/1.1 204 No Content\r\n\
+                            Set-Cookie: urbauth-~zod=0v5.abcde; Path=/; Max-Age=604800\r\n\r\n";
+                respond(stream, resp)
+            } else if request.is("GET", "/") {
+                if !respond(stream, EVENT_STREAM) {
+                    return false;
+                }
+                let chunks = chunks.lock().unwrap().take().unwrap();
+                for chunk in chunks {
+                    if stream.write_all(&chunk).and(stream.flush()).is_err() {
+                        break;
+                    }
+                    // Keep the chunks apart, so that events split across
+                    // them are read in pieces
+                    thread::sleep(Duration::from_millis(20));
+                }
+                false
+            } else {
+                if let Some(json) = request.json() {
+                    let _ = put_sender.lock().unwrap().send(json);
+                }
+                respond(stream, NO_CONTENT)
+            }
+        });
+        (url, chunk_sender, puts)
+    }
+
+    // The actions of every PUT the fake ship has received so far
+    fn sent_actions(puts: &Receiver<JsonValue>) -> Vec<JsonValue> {
+        puts.try_iter()
+            .flat_map(|put| put.members().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    // Verify that subscriptions wait for their watch ack, and that every
+    // event read from the SSE connection is acked, even ones which are not
+    // facts
+    fn subscribes_and_acks_every_event() {
+        let (url, chunks, puts) = fake_ship();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let ship_interface = AsyncShipInterface::new(&url, "code").await.unwrap();
+            assert_eq!(ship_interface.ship_name, "zod");
+            let mut channel = ship_interface.create_channel().await.unwrap();
+
+            // The ack of the opening poke, the watch ack, and a fact split
+            // across two chunks
+            let creation_id = channel.message_id_count;
+            for chunk in &[
+                "id: 1\ndata: {\"id\": 1, \"response\": \"poke\", \"ok\": \"ok\"}\n\n".to_string(),
+                format!(
+                    "id: 2\ndata: {{\"id\": {}, \"response\": \"subscribe\", \"ok\": \"ok\"}}\n\n",
+                    creation_id
+                ),
+                format!("id: 3\ndata: {{\"id\": {}, \"resp", creation_id),
+                "onse\": \"diff\", \"json\": {\"a\": 1}}\n\n".to_string(),
+            ] {
+                chunks.send(chunk.as_bytes().to_vec()).unwrap();
+            }
+            assert_eq!(
+                channel
+                    .create_new_subscription("app", "/path")
+                    .await
+                    .unwrap(),
+                creation_id
+            );
+            assert_eq!(channel.subscription_list.len(), 1);
+            let message = channel.next_message().await.unwrap().unwrap();
+            assert_eq!(message.creation_id, creation_id);
+            assert_eq!(message.message, "{\"a\":1}");
+
+            // A nacked subscription is not added
+            let nacked_id = channel.message_id_count;
+            let nack = format!(
+                "id: 4\ndata: {{\"id\": {}, \"response\": \"subscribe\", \"err\": \"trace\"}}\n\n",
+                nacked_id
+            );
+            chunks.send(nack.into_bytes()).unwrap();
+            match channel.create_new_subscription("app", "/nacked").await {
+                Err(UrbitAPIError::SubscriptionNacked(trace)) => assert_eq!(trace, "trace"),
+                res => panic!("Unexpected subscribe result: {:?}", res),
+            }
+
+            // A subscription which is never acked is ended on the ship
+            channel.ack_timeout = Duration::from_millis(200);
+            let lost_id = channel.message_id_count;
+            match channel.create_new_subscription("app", "/lost").await {
+                Err(UrbitAPIError::AckTimeout(id)) => assert_eq!(id, lost_id),
+                res => panic!("Unexpected subscribe result: {:?}", res),
+            }
+            assert_eq!(channel.subscription_list.len(), 1);
+
+            let actions = sent_actions(&puts);
+            let acked: Vec<u64> = actions
+                .iter()
+                .filter(|a| a["action"] == "ack")
+                .filter_map(|a| a["event-id"].as_u64())
+                .collect();
+            assert_eq!(acked, vec![1, 2, 3, 4]);
+            assert!(actions
+                .iter()
+                .any(|a| a["action"] == "unsubscribe" && a["subscription"] == lost_id));
+
+            channel.delete_channel().await.unwrap();
+            assert!(sent_actions(&puts).iter().any(|a| a["action"] == "delete"));
+        });
+    }
+
+    #[test]
+    // Verify that a channel opened with a subscription which its app nacks
+    // is deleted
+    fn deletes_channel_with_nacked_opening_subscription() {
+        let (url, chunks, puts) = fake_ship();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let ship_interface = AsyncShipInterface::new(&url, "code").await.unwrap();
+            let options = ChannelOptions::default().open_action(OpenAction::Subscribe {
+                ship: None,
+                app: "app".to_string(),
+                path: "/path".to_string(),
+            });
+            let nack =
+                "id: 1\ndata: {\"id\": 1, \"response\": \"subscribe\", \"err\": \"trace\"}\n\n";
+            chunks.send(nack.as_bytes().to_vec()).unwrap();
+            match ship_interface.create_channel_with_options(options).await {
+                Err(UrbitAPIError::SubscriptionNacked(trace)) => assert_eq!(trace, "trace"),
+                res => panic!("Unexpected channel result: {:?}", res.map(|c| c.uid)),
+            }
+            assert!(sent_actions(&puts).iter().any(|a| a["action"] == "delete"));
+        });
+    }
+}
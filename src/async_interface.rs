@@ -0,0 +1,92 @@
+use crate::async_channel::AsyncChannel;
+use crate::cookie::SessionCookie;
+use crate::error::{Result, UrbitAPIError};
+use crate::options::ChannelOptions;
+use json::JsonValue;
+use reqwest::header::{HeaderValue, ACCEPT, COOKIE};
+use reqwest::{Client, Response};
+use std::time::SystemTime;
+
+/// The async counterpart of `ShipInterface`, which holds the details for
+/// connecting to a given Urbit ship
+#[derive(Debug, Clone)]
+pub struct AsyncShipInterface {
+    /// The URL of the ship given as `http://ip:port` such as
+    /// `http://0.0.0.0:8080`.
+    pub url: String,
+    /// The session auth string header value
+    pub session_auth: HeaderValue,
+    /// The ship name
+    pub ship_name: String,
+    /// The async Reqwest `Client` to be reused for making requests
+    req_client: Client,
+}
+
+impl AsyncShipInterface {
+    /// Logs into the given ship and creates a new `AsyncShipInterface`.
+    /// `ship_url` should be `http://ip:port` of the given ship. Example:
+    /// `http://0.0.0.0:8080`. `ship_code` is the code acquire from your ship
+    /// by typing `+code` in dojo.
+    pub async fn new(ship_url: &str, ship_code: &str) -> Result<AsyncShipInterface> {
+        let client = Client::new();
+        let login_url = format!("{}/~/login", ship_url);
+        let resp = client
+            .post(&login_url)
+            .body(format!("password={}", ship_code))
+            .send()
+            .await?;
+
+        // Check for status code
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToLogin);
+        }
+
+        // Parse the session auth cookie out of the response
+        let cookie = SessionCookie::from_headers(resp.headers(), SystemTime::now())?;
+
+        Ok(AsyncShipInterface {
+            url: ship_url.to_string(),
+            session_auth: cookie.header_value()?,
+            ship_name: cookie.ship_name,
+            req_client: client,
+        })
+    }
+
+    /// Create an `AsyncChannel` using this `AsyncShipInterface`
+    pub async fn create_channel(&self) -> Result<AsyncChannel> {
+        AsyncChannel::new(self.clone()).await
+    }
+
+    /// Create an `AsyncChannel` using this `AsyncShipInterface` with the
+    /// given `ChannelOptions`
+    pub async fn create_channel_with_options(
+        &self,
+        options: ChannelOptions,
+    ) -> Result<AsyncChannel> {
+        AsyncChannel::with_options(self.clone(), options).await
+    }
+
+    /// Send a put request using the `AsyncShipInterface`
+    pub async fn send_put_request(&self, url: &str, body: &JsonValue) -> Result<Response> {
+        let json = body.dump();
+        let resp = self
+            .req_client
+            .put(url)
+            .header(COOKIE, self.session_auth.clone())
+            .header("Content-Type", "application/json")
+            .body(json);
+
+        Ok(resp.send().await?)
+    }
+
+    /// Open the SSE connection of a channel using the `AsyncShipInterface`
+    pub(crate) async fn send_event_stream_request(&self, url: &str) -> Result<Response> {
+        let resp = self
+            .req_client
+            .get(url)
+            .header(COOKIE, self.session_auth.clone())
+            .header(ACCEPT, "text/event-stream");
+
+        Ok(resp.send().await?)
+    }
+}
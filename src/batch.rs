@@ -0,0 +1,123 @@
+use crate::subscription::{CreationID, Subscription};
+use json::JsonValue;
+use std::time::{Duration, Instant};
+
+/// When a `Channel` flushes its queued actions on its own, without waiting
+/// for `flush` to be called
+#[derive(Debug, Clone, Default)]
+pub struct BatchPolicy {
+    /// Flush once this many actions are queued
+    pub max_actions: Option<usize>,
+    /// Flush once the oldest queued action has waited this long
+    pub max_delay: Option<Duration>,
+}
+
+// A batch of channel actions which are sent to the ship in a single PUT,
+// together with the changes to the `subscription_list` which are applied
+// once the batch has been sent.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ActionBatch {
+    /// The queued actions, in the order they are sent
+    actions: Vec<JsonValue>,
+    /// When the oldest queued action was queued
+    started_at: Option<Instant>,
+    /// The `Subscription`s created by the queued subscribe actions
+    pub(crate) subscriptions: Vec<Subscription>,
+    /// The `creation_id`s of the `Subscription`s removed by the queued
+    /// unsubscribe actions
+    pub(crate) unsubscriptions: Vec<CreationID>,
+}
+
+impl ActionBatch {
+    /// Queues an action
+    pub fn push(&mut self, action: JsonValue) {
+        if self.actions.is_empty() {
+            self.started_at = Some(Instant::now());
+        }
+        self.actions.push(action);
+    }
+
+    /// The number of queued actions
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Whether no actions are queued
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Whether the batch has reached one of the thresholds of `policy`
+    pub fn is_due(&self, policy: &BatchPolicy) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let too_many = policy
+            .max_actions
+            .is_some_and(|max| self.actions.len() >= max);
+        too_many || self.due_in(policy) == Some(Duration::from_secs(0))
+    }
+
+    /// How long until the oldest queued action has waited the `max_delay`
+    /// of `policy`, if actions are queued and it is set
+    pub fn due_in(&self, policy: &BatchPolicy) -> Option<Duration> {
+        match (policy.max_delay, self.started_at) {
+            (Some(max), Some(started_at)) if !self.is_empty() => {
+                Some(max.saturating_sub(started_at.elapsed()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Restarts the wait for the `max_delay` of a batch which failed to be
+    /// sent, so that it is not retried right away
+    pub fn restart_delay(&mut self) {
+        self.started_at = Some(Instant::now());
+    }
+
+    /// The json array of the queued actions, as sent in the PUT
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.actions.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    #[test]
+    // Verify that a batch is due once it holds `max_actions` actions
+    fn is_due_on_size() {
+        let policy = BatchPolicy {
+            max_actions: Some(2),
+            max_delay: None,
+        };
+        let mut batch = ActionBatch::default();
+        assert!(!batch.is_due(&policy));
+        batch.push(object! { "id": 1, "action": "ack", "event-id": 1 });
+        assert!(!batch.is_due(&policy));
+        batch.push(object! { "id": 2, "action": "ack", "event-id": 2 });
+        assert!(batch.is_due(&policy));
+        assert_eq!(batch.to_json().len(), 2);
+    }
+
+    #[test]
+    // Verify that a batch is due once its oldest action has waited
+    // `max_delay`
+    fn is_due_on_delay() {
+        let policy = BatchPolicy {
+            max_actions: None,
+            max_delay: Some(Duration::from_millis(20)),
+        };
+        let mut batch = ActionBatch::default();
+        assert_eq!(batch.due_in(&policy), None);
+        batch.push(object! { "id": 1, "action": "ack", "event-id": 1 });
+        assert!(!batch.is_due(&policy));
+        assert!(batch.due_in(&policy).unwrap() <= Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(batch.is_due(&policy));
+        batch.restart_delay();
+        assert!(!batch.is_due(&policy));
+    }
+}
@@ -1,96 +1,382 @@
+use crate::ack::{AckPolicy, AckTracker};
+use crate::batch::{ActionBatch, BatchPolicy};
 use crate::error::{Result, UrbitAPIError};
-use crate::interface::ShipInterface;
-use crate::subscription::{CreationID, Subscription};
-use eventsource_threaded::{EventSource, ReceiverSource};
-use json::object;
-use rand::Rng;
+use crate::event::ChannelEvent;
+use crate::interface::{ChannelRegistration, ShipInterface};
+use crate::options::ChannelOptions;
+use crate::pump::ChannelPump;
+use crate::retry::RetryPolicy;
+use crate::sse::{self, Event, ReceiverSource, SseMessage};
+use crate::subscription::{CreationID, Subscription, SubscriptionMessage};
+#[cfg(feature = "serde")]
+use crate::typed;
+use json::{object, JsonValue};
 use reqwest::blocking::Response;
-use reqwest::header::HeaderMap;
-use reqwest::Url;
-use std::time::SystemTime;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-// A Channel which is used to interact with a ship
-// #[derive(Clone)]
-pub struct Channel<'a> {
+/// What happened after an app kicked one of the `Subscription`s of a
+/// `Channel`
+#[derive(Debug)]
+pub enum QuitOutcome {
+    /// The `Subscription` was resubscribed. Messages sent while it was
+    /// kicked may have been missed.
+    Resubscribed,
+    /// Resubscribing failed and the `Subscription` was removed from the
+    /// `subscription_list`.
+    Removed(UrbitAPIError),
+}
+
+/// A `Subscription` which has been kicked and is waiting to be resubscribed
+#[derive(Debug)]
+struct QuitSubscription {
+    /// The `creation_id` of the `Subscription`
+    creation_id: CreationID,
+    /// The number of attempts to resubscribe which have failed
+    retries: u32,
+    /// When the next attempt to resubscribe is due
+    retry_at: Instant,
+}
+
+/// A callback which is called after a `Subscription` is kicked
+pub type QuitHandler = Box<dyn FnMut(&Subscription, &QuitOutcome) + Send>;
+
+/// How long the message iterators of a `Channel` wait for each message
+/// before checking that the connection is still usable and waiting again
+const MESSAGE_WAIT: Duration = Duration::from_secs(60);
+
+/// A callback which is called with every fact sent to a `Subscription`
+pub type FactHandler = Box<dyn FnMut(&Subscription, &str) + Send>;
+
+/// A callback which is called with the ack, or the nack's error trace, of
+/// every poke sent over a `Channel`
+pub type PokeAckHandler = Box<dyn FnMut(u64, &std::result::Result<(), String>) + Send>;
+
+/// A callback which is called when the SSE connection of a `Channel` goes
+/// stale
+pub type StaleHandler = Box<dyn FnMut(&ConnectionStatus) + Send>;
+
+/// The state of the SSE connection of a `Channel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The SSE connection is being opened or reopened
+    Connecting,
+    /// The SSE connection is open and events or heartbeats are arriving
+    Open,
+    /// The SSE connection is open, but nothing has arrived within the
+    /// `stale_after` window
+    Stale,
+    /// The SSE connection has closed and will not be reopened
+    Closed,
+}
+
+/// What `Channel::receive_event` received from the SSE connection
+enum Received {
+    /// Nothing arrived before the timeout
+    Nothing,
+    /// The SSE connection was opened or closed
+    ConnectionChange,
+    /// Data which held no event, such as a heartbeat, arrived
+    Heartbeat,
+    /// An event with the given id arrived and was processed
+    Event(u64, ChannelEvent),
+}
+
+/// The status of the SSE connection of a `Channel`
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStatus {
+    /// Whether the SSE connection is currently open
+    pub connected: bool,
+    /// The number of times the SSE connection has been reopened after
+    /// dropping
+    pub reconnects: u64,
+    /// The number of reconnection attempts which have failed since the
+    /// SSE connection was last open
+    pub failed_attempts: u32,
+    /// The id of the last event which was received. It is sent as the
+    /// `Last-Event-ID` when reconnecting, so the ship resends every event
+    /// after it.
+    pub last_event_id: Option<u64>,
+    /// The error which most recently closed the SSE connection
+    pub last_error: Option<String>,
+    /// The number of requests carrying acks which have failed. The acks
+    /// of a failed request are retried with the next ack.
+    pub failed_acks: u64,
+    /// The error of the most recent failed request carrying acks
+    pub last_ack_error: Option<String>,
+    /// The number of batches of queued actions which have failed to send.
+    /// The actions of a failed batch stay queued and are sent again.
+    pub failed_batches: u64,
+    /// The error of the most recent batch which failed to send
+    pub last_batch_error: Option<String>,
+    /// When an event or heartbeat last arrived on the SSE connection
+    pub last_activity: Option<Instant>,
+}
+
+// A Channel which is used to interact with a ship. A `Channel` owns a
+// handle to the session of the `ShipInterface` it was created from, and is
+// `Send + Sync`. Its callbacks only need to be `Send`, and are kept in
+// `Mutex`es which are only ever reached through `&mut Channel`.
+pub struct Channel {
     /// `ShipInterface` this channel is created from
-    pub ship_interface: &'a ShipInterface,
+    pub ship_interface: ShipInterface,
     /// The uid of the channel
     pub uid: String,
     /// The url of the channel
     pub url: String,
     // The list of `Subscription`s for this channel
     pub subscription_list: Vec<Subscription>,
-    // / The `EventSource` for this channel which reads all of
-    // / the SSE events.
-    event_receiver: ReceiverSource,
+    // / The receiver of the SSE connection for this channel which reads
+    // / all of the SSE events.
+    event_receiver: Mutex<ReceiverSource>,
     /// The current number of messages that have been sent out (which are
     /// also defined as message ids) via this `Channel`
     pub message_id_count: u64,
+    /// How long to wait for the ship to ack a new subscription
+    pub ack_timeout: Duration,
+    /// How `Subscription`s are resubscribed after being kicked
+    pub resubscribe_policy: RetryPolicy,
+    /// The ids of messages which are waiting to be acked by the ship,
+    /// together with the ack (or the nack's error trace) once it arrives
+    pending_acks: HashMap<u64, Option<std::result::Result<(), String>>>,
+    /// The `Subscription`s which have been kicked and are waiting to be
+    /// resubscribed
+    quit_subscriptions: Vec<QuitSubscription>,
+    /// Called after a `Subscription` is kicked
+    quit_handler: Option<Mutex<QuitHandler>>,
+    /// Called with the facts of the `Subscription` with the given
+    /// `creation_id`, instead of adding them to its `message_list`
+    fact_handlers: HashMap<CreationID, Mutex<FactHandler>>,
+    /// Called with the ack of every poke
+    poke_ack_handler: Option<Mutex<PokeAckHandler>>,
+    /// How the SSE connection is reopened after it drops
+    pub reconnect_policy: RetryPolicy,
+    /// The status of the SSE connection
+    status: ConnectionStatus,
+    /// When the SSE connection is due to be reopened, if it has dropped
+    reconnect_at: Option<Instant>,
+    /// The actions queued to be sent in a single PUT
+    batch: ActionBatch,
+    /// The `creation_id`s of `Subscription`s which were sent in a batch and
+    /// are waiting to be acked by their app
+    unacked_subscriptions: Vec<CreationID>,
+    /// When the queued actions are flushed without calling `flush`
+    pub batch_policy: BatchPolicy,
+    /// How the SSE events received by this channel are acked
+    pub ack_policy: AckPolicy,
+    /// The events which have not been acked yet
+    acks: AckTracker,
+    /// Whether the SSE connection is being opened
+    connecting: bool,
+    /// How long the SSE connection may go without an event or heartbeat
+    /// before it is considered stale, or `None` to never consider it stale
+    pub stale_after: Option<Duration>,
+    /// Whether the SSE connection is reopened once it goes stale
+    pub reconnect_when_stale: bool,
+    /// Called when the SSE connection goes stale
+    stale_handler: Option<Mutex<StaleHandler>>,
+    /// Whether the SSE connection has been reported stale since the last
+    /// activity on it
+    stale_reported: bool,
+    /// Whether the channel is deleted on the ship, after sending the queued
+    /// actions and unsubscribing from every `Subscription`, when the
+    /// `Channel` is dropped without calling `delete_channel`, or after
+    /// `delete_channel` failed
+    pub delete_on_drop: bool,
+    /// The state shared with the session, which records whether the
+    /// channel has been deleted on the ship, including by
+    /// `ShipInterface::logout`
+    registration: ChannelRegistration,
+    /// The most recent error hit while reading events which could not be
+    /// returned, such as a failed automatic flush or resubscription, until
+    /// a `ChannelPump` reports it
+    unreported_error: Option<UrbitAPIError>,
 }
 
-impl<'a> Channel<'a> {
+impl Channel {
     /// Create a new channel
-    pub fn new(ship_interface: &mut ShipInterface) -> Result<Channel> {
-        let mut rng = rand::thread_rng();
-        // Defining the uid as UNIX time, or random if error
-        let uid = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => n.as_secs(),
-            Err(_) => rng.gen(),
-        }
-        .to_string();
+    pub fn new(ship_interface: ShipInterface) -> Result<Channel> {
+        Channel::with_options(ship_interface, ChannelOptions::default())
+    }
+
+    /// Create a new channel with the given `ChannelOptions`. If the channel
+    /// is opened with `OpenAction::Subscribe`, waits up to `ack_timeout`
+    /// for the app to ack the subscription like `create_new_subscription`,
+    /// and deletes the channel if it is not acked.
+    pub fn with_options(ship_interface: ShipInterface, options: ChannelOptions) -> Result<Channel> {
+        let uid = options.new_uid();
 
         // Channel url
         let channel_url = format!("{}/~/channel/{}", &ship_interface.url, uid);
         // Opening channel request json
         let mut body = json::parse(r#"[]"#).unwrap();
-        body[0] = object! {
-                "id": 1,
-                "action": "poke",
-                "ship": ship_interface.ship_name.clone(),
-                "app": "hood",
-                "mark": "helm-hi",
-                "json": "Opening channel",
-        };
+        body[0] = options.open_action.to_json(&ship_interface.ship_name);
+        // The `Subscription` created by the opening action, if any
+        let subscription = options
+            .open_action
+            .subscription(&uid, &ship_interface.ship_name);
 
         // Make the put request to create the channel.
         let resp = ship_interface.send_put_request(&channel_url, &body)?;
 
-        if resp.status().as_u16() == 204 {
-            // Create cookie header with the ship session auth val
-            let mut headers = HeaderMap::new();
-            headers.append("cookie", ship_interface.session_auth.clone());
-            // Create the receiver
-            let receiver = EventSource::new(Url::parse(&channel_url).unwrap(), headers);
-
-            return Ok(Channel {
-                ship_interface: ship_interface,
-                uid: uid,
-                url: channel_url,
-                subscription_list: vec![],
-                event_receiver: receiver,
-                message_id_count: 2,
-            });
-        } else {
+        if resp.status().as_u16() != 204 {
             return Err(UrbitAPIError::FailedToCreateNewChannel);
         }
+
+        // Create the receiver
+        let receiver = ship_interface.open_event_stream(&channel_url, None);
+        let mut channel = Channel::from_parts(ship_interface, uid, channel_url, vec![], receiver);
+        channel.registration = channel
+            .ship_interface
+            .register_channel(&channel.url, channel.message_id_count);
+
+        // Wait for the app to accept the opening subscription
+        if let Some(sub) = subscription {
+            let err = match channel.wait_for_ack(sub.creation_id, channel.ack_timeout) {
+                Ok(Ok(())) => {
+                    channel.subscription_list.push(sub);
+                    return Ok(channel);
+                }
+                Ok(Err(trace)) => UrbitAPIError::SubscriptionNacked(trace),
+                Err(e) => e,
+            };
+            // Deleting the channel also ends the subscription if it was
+            // accepted after all. The error of the subscription is the one
+            // worth returning.
+            let _ = channel.delete_channel();
+            return Err(err);
+        }
+        Ok(channel)
     }
 
-    /// Acquires and returns the current `message_id_count` from the
-    /// `ShipInterface` that this channel was created from while also
-    /// increase said value by 1.
+    /// Builds a `Channel` which has been created on the ship, with the
+    /// receiver of its SSE connection
+    fn from_parts(
+        ship_interface: ShipInterface,
+        uid: String,
+        url: String,
+        subscription_list: Vec<Subscription>,
+        receiver: ReceiverSource,
+    ) -> Channel {
+        Channel {
+            ship_interface,
+            uid,
+            url,
+            subscription_list,
+            event_receiver: Mutex::new(receiver),
+            message_id_count: 2,
+            ack_timeout: Duration::from_secs(30),
+            resubscribe_policy: RetryPolicy::default(),
+            pending_acks: HashMap::new(),
+            quit_subscriptions: vec![],
+            quit_handler: None,
+            fact_handlers: HashMap::new(),
+            poke_ack_handler: None,
+            reconnect_policy: RetryPolicy {
+                max_retries: None,
+                ..RetryPolicy::default()
+            },
+            status: ConnectionStatus::default(),
+            reconnect_at: None,
+            batch: ActionBatch::default(),
+            unacked_subscriptions: vec![],
+            batch_policy: BatchPolicy::default(),
+            ack_policy: AckPolicy::default(),
+            acks: AckTracker::default(),
+            connecting: true,
+            stale_after: None,
+            reconnect_when_stale: false,
+            stale_handler: None,
+            stale_reported: false,
+            delete_on_drop: false,
+            registration: ChannelRegistration::default(),
+            unreported_error: None,
+        }
+    }
+
+    /// Acquires and returns the current `message_id_count` of this channel
+    /// while also increase said value by 1.
     pub fn get_and_raise_message_id_count(&mut self) -> u64 {
         let current_id_count = self.message_id_count;
         self.message_id_count += 1;
+        self.registration.record_next_id(self.message_id_count);
         current_id_count
     }
 
     /// Sends a poke over the channel
     pub fn poke(&mut self, app: &str, mark: &str, json: &str) -> Result<Response> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.poke_on_ship(&ship, app, mark, json)
+    }
+
+    /// Sends a poke over the channel to an app on the given ship, which may
+    /// be a foreign ship reached through the ship of this channel
+    pub fn poke_on_ship(
+        &mut self,
+        ship: &str,
+        app: &str,
+        mark: &str,
+        json: &str,
+    ) -> Result<Response> {
+        let id = self.get_and_raise_message_id_count();
+        self.send_poke(id, ship, app, mark, json.into())
+    }
+
+    /// Sends a poke over the channel with `data` serialized as the
+    /// structured json of the poke
+    #[cfg(feature = "serde")]
+    pub fn poke_typed<T: Serialize>(
+        &mut self,
+        app: &str,
+        mark: &str,
+        data: &T,
+    ) -> Result<Response> {
+        let json = typed::to_json_value(data)?;
+        let id = self.get_and_raise_message_id_count();
+        let ship = self.ship_interface.ship_name.clone();
+        self.send_poke(id, &ship, app, mark, json)
+    }
+
+    /// Sends a poke over the channel and waits up to `timeout` for the app
+    /// to ack it, or forever if `timeout` is `Duration::MAX`. If the app
+    /// nacks the poke, the error trace is returned as
+    /// `UrbitAPIError::PokeNacked`.
+    pub fn poke_and_wait(
+        &mut self,
+        app: &str,
+        mark: &str,
+        json: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let id = self.get_and_raise_message_id_count();
+        let ship = self.ship_interface.ship_name.clone();
+        let resp = self.send_poke(id, &ship, app, mark, json.into())?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToPoke);
+        }
+        self.wait_for_ack(id, timeout)?
+            .map_err(UrbitAPIError::PokeNacked)
+    }
+
+    /// Sends a poke with the given message id over the channel
+    fn send_poke(
+        &self,
+        id: u64,
+        ship: &str,
+        app: &str,
+        mark: &str,
+        json: JsonValue,
+    ) -> Result<Response> {
         let mut body = json::parse(r#"[]"#).unwrap();
         body[0] = object! {
-                "id": self.get_and_raise_message_id_count(),
+                "id": id,
                 "action": "poke",
-                "ship": self.ship_interface.ship_name.clone(),
+                "ship": ship_without_sig(ship),
                 "app": app,
                 "mark": mark,
                 "json": json,
@@ -101,8 +387,44 @@ impl<'a> Channel<'a> {
     }
 
     /// Create a new `Subscription` and thus subscribes to events on the
-    /// ship with the provided app/path.
+    /// ship with the provided app/path. Waits up to `ack_timeout` for the
+    /// app to ack the subscription, and only then adds it to the
+    /// `subscription_list`. If the app nacks the subscription, the error
+    /// trace is returned as `UrbitAPIError::SubscriptionNacked`. If no ack
+    /// arrives in time, the ship is told to end the subscription and
+    /// `UrbitAPIError::AckTimeout` is returned.
     pub fn create_new_subscription(&mut self, app: &str, path: &str) -> Result<CreationID> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.create_new_subscription_on_ship(&ship, app, path)
+    }
+
+    /// Create a new `Subscription` to the provided app/path on the given
+    /// ship, which may be a foreign ship reached through the ship of this
+    /// channel. Otherwise works like `create_new_subscription`.
+    pub fn create_new_subscription_on_ship(
+        &mut self,
+        ship: &str,
+        app: &str,
+        path: &str,
+    ) -> Result<CreationID> {
+        let creation_id = self.subscribe(ship, app, path)?;
+        // Create the `Subscription`
+        let sub = Subscription {
+            channel_uid: self.uid.clone(),
+            creation_id,
+            ship: ship_without_sig(ship),
+            app: app.to_string(),
+            path: path.to_string(),
+            message_list: vec![],
+        };
+        // Add the `Subscription` to the list
+        self.subscription_list.push(sub);
+        Ok(creation_id)
+    }
+
+    /// Subscribes to events on the given ship with the provided app/path
+    /// and waits up to `ack_timeout` for the app to ack the subscription.
+    fn subscribe(&mut self, ship: &str, app: &str, path: &str) -> Result<CreationID> {
         // Saves the message id to be reused
         let creation_id = self.get_and_raise_message_id_count();
         // Create the json body
@@ -110,103 +432,1678 @@ impl<'a> Channel<'a> {
         body[0] = object! {
                 "id": creation_id,
                 "action": "subscribe",
-                "ship": self.ship_interface.ship_name.clone(),
+                "ship": ship_without_sig(ship),
                 "app": app.to_string(),
                 "path": path.to_string(),
         };
 
-        // Make the put request to create the channel.
+        // Make the put request to create the subscription.
         let resp = self.ship_interface.send_put_request(&self.url, &body)?;
-
-        if resp.status().as_u16() == 204 {
-            // Create the `Subscription`
-            let sub = Subscription {
-                channel_uid: self.uid.clone(),
-                creation_id: creation_id,
-                app: app.to_string(),
-                path: path.to_string(),
-                message_list: vec![],
-            };
-            // Add the `Subscription` to the list
-            self.subscription_list.push(sub.clone());
-            return Ok(creation_id);
-        } else {
+        if resp.status().as_u16() != 204 {
             return Err(UrbitAPIError::FailedToCreateNewSubscription);
         }
+
+        // Wait for the app to accept the subscription
+        let ack = match self.wait_for_ack(creation_id, self.ack_timeout) {
+            Err(UrbitAPIError::AckTimeout(id)) => {
+                // The watch may still be accepted once the ack is lost, so
+                // leave it rather than having it send facts nobody reads
+                let _ = self.send_unsubscribe(creation_id);
+                return Err(UrbitAPIError::AckTimeout(id));
+            }
+            ack => ack?,
+        };
+        ack.map_err(UrbitAPIError::SubscriptionNacked)?;
+        Ok(creation_id)
+    }
+
+    /// Tells the ship to end the subscription with the given `creation_id`
+    fn send_unsubscribe(&mut self, creation_id: CreationID) -> Result<()> {
+        let mut json = json::parse(r#"[]"#).unwrap();
+        json[0] = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "unsubscribe",
+            "subscription": creation_id,
+        };
+        let resp = self.ship_interface.send_put_request(&self.url, &json)?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToUnsubscribe);
+        }
+        Ok(())
+    }
+
+    /// Sets the callback which is called after a `Subscription` is kicked
+    /// by its app, once it has been resubscribed or removed according to
+    /// the `resubscribe_policy`. Failed attempts are retried as the channel
+    /// keeps reading events, rather than sleeping until the retry is due.
+    pub fn on_quit(&mut self, handler: impl FnMut(&Subscription, &QuitOutcome) + Send + 'static) {
+        self.quit_handler = Some(Mutex::new(Box::new(handler)));
+    }
+
+    /// Create a new `Subscription` like `create_new_subscription`, with
+    /// `handler` called with every fact sent to it instead of adding them
+    /// to its `message_list`.
+    pub fn create_new_subscription_with_handler(
+        &mut self,
+        app: &str,
+        path: &str,
+        handler: impl FnMut(&Subscription, &str) + Send + 'static,
+    ) -> Result<CreationID> {
+        let creation_id = self.create_new_subscription(app, path)?;
+        self.on_fact(creation_id, handler);
+        Ok(creation_id)
+    }
+
+    /// Sets the callback which is called with every fact sent to the
+    /// `Subscription` with the given `creation_id`, instead of adding them
+    /// to its `message_list`. The callback follows the `Subscription` when
+    /// it is resubscribed after being kicked.
+    pub fn on_fact(
+        &mut self,
+        creation_id: CreationID,
+        handler: impl FnMut(&Subscription, &str) + Send + 'static,
+    ) {
+        self.fact_handlers
+            .insert(creation_id, Mutex::new(Box::new(handler)));
+    }
+
+    /// Sets the callback which is called with the ack, or the nack's error
+    /// trace, of every poke sent over the channel
+    pub fn on_poke_ack(
+        &mut self,
+        handler: impl FnMut(u64, &std::result::Result<(), String>) + Send + 'static,
+    ) {
+        self.poke_ack_handler = Some(Mutex::new(Box::new(handler)));
     }
 
     /// Parses SSE messages for this channel and moves them into
     /// the proper corresponding `Subscription`'s `message_list`.
     pub fn parse_event_messages(&mut self) {
-        let rec = &mut self.event_receiver;
+        self.parse_events();
+    }
 
+    /// Parses SSE messages for this channel like `parse_event_messages`,
+    /// and also returns every event which was parsed so that they can be
+    /// matched on by kind, together with the id of its SSE event, which is
+    /// what `ack` takes.
+    pub fn parse_events(&mut self) -> Vec<(u64, ChannelEvent)> {
+        let mut events = vec![];
         // Consume all messages
         loop {
-            if let Ok(event_res) = rec.try_recv() {
-                if let Err(e) = &event_res {
-                    println!("Error Event: {}", e);
-                }
-                if let Ok(event) = event_res {
-                    // Go through all subscriptions and find which
-                    // subscription this event is for.
-                    for sub in &mut self.subscription_list {
-                        // If adding the message succeeded (because found
-                        // correct `Subscription`) then stop.
-                        if let Some(_) = sub.add_to_message_list(&event) {
-                            // Send an ack for the processed event
-                            // Using unwrap because `add_to_message_list`
-                            // already does error checking.
-                            let eid: u64 = event.id.unwrap().parse().unwrap();
-                            let mut json = json::parse(r#"[]"#).unwrap();
-                            json[0] = object! {
-                                "id": self.message_id_count,
-                                "action": "ack",
-                                "event-id": eid,
-                            };
-                            self.message_id_count += 1;
-                            let ack_res = self.ship_interface.send_put_request(&self.url, &json);
-                            break;
+            match self.receive_event(Duration::from_secs(0)) {
+                Ok(Received::Event(eid, event)) => events.push((eid, event)),
+                Ok(Received::ConnectionChange) | Ok(Received::Heartbeat) => {}
+                Ok(Received::Nothing) | Err(_) => break,
+            }
+        }
+        self.resubscribe_quit_subscriptions();
+        // Send the acks of every parsed event in one request. The queued
+        // actions are left for `flush` or the `batch_policy`, and a failed
+        // ack is recorded in the `ConnectionStatus` and retried.
+        let _ = self.flush_acks();
+        events
+    }
+
+    /// Blocks for up to `timeout` until a message arrives for the
+    /// `Subscription` with a matching `app` and `path` on the ship of this
+    /// channel, and pops it. Returns `None` if no message arrived in time,
+    /// `UrbitAPIError::SubscriptionNotFound` if there is no such
+    /// subscription, and `UrbitAPIError::FactsSentToHandler` if its facts
+    /// go to a handler instead of its `message_list`. Wakes as soon as new
+    /// SSE data arrives. The events read while waiting, such as those for
+    /// other subscriptions, are acked as they come due under the
+    /// `ack_policy`, so a long wait on a quiet subscription does not clog
+    /// the channel.
+    pub fn next_message(
+        &mut self,
+        app: &str,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        let creation_id = match self.find_subscription(app, path) {
+            Some(sub) => sub.creation_id,
+            None => {
+                return Err(UrbitAPIError::SubscriptionNotFound(
+                    app.to_string(),
+                    path.to_string(),
+                ))
+            }
+        };
+        if self.fact_handlers.contains_key(&creation_id) {
+            return Err(UrbitAPIError::FactsSentToHandler(
+                app.to_string(),
+                path.to_string(),
+            ));
+        }
+        let arrived = self.wait_until(timeout, |channel| {
+            channel.resubscribe_quit_subscriptions();
+            channel
+                .find_subscription(app, path)
+                .filter(|sub| !sub.message_list.is_empty())
+                .map(|_| ())
+        })?;
+        // Send the acks of the events read while waiting before popping the
+        // message, so that it stays queued if the ack fails
+        self.flush_acks()?;
+        Ok(arrived.and_then(|()| {
+            self.find_subscription(app, path)
+                .and_then(|sub| sub.pop_message())
+        }))
+    }
+
+    /// Blocks for up to `timeout` until a message arrives for any of the
+    /// `Subscription`s in the `subscription_list`, and pops it. Returns
+    /// `None` if no message arrived in time, and
+    /// `UrbitAPIError::FactsSentToHandler` if the facts of every
+    /// `Subscription` go to handlers. Events are acked while waiting like
+    /// `next_message`.
+    pub fn next_any_message(&mut self, timeout: Duration) -> Result<Option<SubscriptionMessage>> {
+        let handled = self
+            .subscription_list
+            .iter()
+            .find(|sub| self.fact_handlers.contains_key(&sub.creation_id));
+        let all_handled = self
+            .subscription_list
+            .iter()
+            .all(|sub| self.fact_handlers.contains_key(&sub.creation_id));
+        if let (Some(sub), true) = (handled, all_handled) {
+            return Err(UrbitAPIError::FactsSentToHandler(
+                sub.app.clone(),
+                sub.path.clone(),
+            ));
+        }
+        let arrived = self.wait_until(timeout, |channel| {
+            channel.resubscribe_quit_subscriptions();
+            channel
+                .subscription_list
+                .iter()
+                .any(|sub| !sub.message_list.is_empty())
+                .then_some(())
+        })?;
+        // Send the acks of the events read while waiting before popping the
+        // message, so that it stays queued if the ack fails
+        self.flush_acks()?;
+        Ok(arrived.and_then(|()| {
+            self.subscription_list
+                .iter_mut()
+                .find_map(|sub| sub.pop_subscription_message())
+        }))
+    }
+
+    /// A blocking `Iterator` over the messages of the `Subscription` with
+    /// a matching `app` and `path` on the ship of this channel. It ends
+    /// after yielding an error, such as once the SSE connection has closed
+    /// and will not be reopened.
+    pub fn messages<'a>(
+        &'a mut self,
+        app: &'a str,
+        path: &'a str,
+    ) -> impl Iterator<Item = Result<String>> + 'a {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            while !done {
+                match self.next_message(app, path, MESSAGE_WAIT) {
+                    Ok(Some(message)) => return Some(Ok(message)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// A blocking `Iterator` over the messages of every `Subscription` in
+    /// the `subscription_list`. It ends after yielding an error, such as
+    /// once the SSE connection has closed and will not be reopened.
+    pub fn all_messages(&mut self) -> impl Iterator<Item = Result<SubscriptionMessage>> + '_ {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            while !done {
+                match self.next_any_message(MESSAGE_WAIT) {
+                    Ok(Some(message)) => return Some(Ok(message)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Waits up to `timeout` for a single message from the SSE connection
+    /// and handles it, calling the handlers of the channel. Kicked
+    /// subscriptions are resubscribed, and queued acks are sent once the
+    /// connection is idle, or once the `ack_policy` says they are due.
+    /// Returns the errors hit along the way, such as failed acks, flushes
+    /// or resubscriptions. This drives a `ChannelPump`.
+    pub(crate) fn pump_events(&mut self, timeout: Duration) -> Result<()> {
+        let received = self.receive_event(timeout);
+        self.resubscribe_quit_subscriptions();
+        if let Ok(Received::Nothing) = received {
+            self.flush_acks()?;
+        }
+        received?;
+        match self.unreported_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the channel to a background `ChannelPump` thread, which reads
+    /// events as they arrive and calls the handlers of the channel. The
+    /// handlers run on the pump thread while it holds the channel, so they
+    /// must not call `ChannelPump::with_channel`. Each attempt to
+    /// resubscribe a kicked `Subscription` also holds the channel while it
+    /// waits up to `ack_timeout` for the ack, blocking `with_channel`.
+    pub fn start_pump(mut self) -> ChannelPump {
+        self.unreported_error = None;
+        ChannelPump::start(self)
+    }
+
+    /// Returns the status of the SSE connection of this channel
+    pub fn connection_status(&self) -> &ConnectionStatus {
+        &self.status
+    }
+
+    /// Returns the state of the SSE connection of this channel. The
+    /// connection only goes `Stale` if `stale_after` is set, and is only
+    /// checked while the channel is reading events, such as in
+    /// `parse_events`.
+    pub fn connection_state(&self) -> ConnectionState {
+        if self.status.connected {
+            if self.is_stale() {
+                ConnectionState::Stale
+            } else {
+                ConnectionState::Open
+            }
+        } else if self.connecting || self.reconnect_at.is_some() {
+            ConnectionState::Connecting
+        } else {
+            ConnectionState::Closed
+        }
+    }
+
+    /// Sets the callback which is called when nothing has arrived on the
+    /// SSE connection within the `stale_after` window. It is called once
+    /// each time the connection goes stale.
+    pub fn on_stale(&mut self, handler: impl FnMut(&ConnectionStatus) + Send + 'static) {
+        self.stale_handler = Some(Mutex::new(Box::new(handler)));
+    }
+
+    /// Whether nothing has arrived on the SSE connection within the
+    /// `stale_after` window
+    fn is_stale(&self) -> bool {
+        match (self.stale_after, self.status.last_activity) {
+            (Some(window), Some(last_activity)) => last_activity.elapsed() >= window,
+            _ => false,
+        }
+    }
+
+    /// Reports the SSE connection once it goes stale, and reopens it if
+    /// `reconnect_when_stale` is set.
+    fn check_liveness(&mut self) {
+        if !self.status.connected || self.stale_reported || !self.is_stale() {
+            return;
+        }
+        self.stale_reported = true;
+        if let Some(handler) = &mut self.stale_handler {
+            exclusive(handler)(&self.status);
+        }
+        if self.reconnect_when_stale {
+            // Drop the wedged connection, which stops the thread reading
+            // it and closes it
+            let (_, _, receiver) = sse::channel();
+            *self
+                .event_receiver
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner()) = receiver;
+            self.schedule_reconnect(UrbitAPIError::ConnectionStale);
+        }
+    }
+
+    /// Records that data arrived on the SSE connection
+    fn record_activity(&mut self) {
+        self.status.last_activity = Some(Instant::now());
+        self.stale_reported = false;
+    }
+
+    /// Resubscribes every `Subscription` which has been kicked and is due
+    /// to be resubscribed. A failed attempt is retried once the delay of
+    /// the `resubscribe_policy` has passed, without blocking in the
+    /// meantime. Subscriptions which cannot be resubscribed are removed
+    /// from the `subscription_list`.
+    fn resubscribe_quit_subscriptions(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.quit_subscriptions)
+            .into_iter()
+            .partition(|q| q.retry_at <= now);
+        self.quit_subscriptions = waiting;
+        for quit in due {
+            let creation_id = quit.creation_id;
+            let (sub, outcome) = match self.resubscribe(creation_id) {
+                Some(Ok(new_id)) => {
+                    let sub = self
+                        .subscription_list
+                        .iter()
+                        .find(|s| s.creation_id == new_id)
+                        .cloned();
+                    (sub, QuitOutcome::Resubscribed)
+                }
+                Some(Err(e)) => {
+                    let retry_at = Instant::now()
+                        .checked_add(self.resubscribe_policy.delay(quit.retries))
+                        .filter(|_| self.resubscribe_policy.should_retry(quit.retries));
+                    if let Some(retry_at) = retry_at {
+                        self.quit_subscriptions.push(QuitSubscription {
+                            creation_id,
+                            retries: quit.retries + 1,
+                            retry_at,
+                        });
+                        continue;
+                    }
+                    self.fact_handlers.remove(&creation_id);
+                    let index = self
+                        .subscription_list
+                        .iter()
+                        .position(|s| s.creation_id == creation_id);
+                    let sub = index.map(|index| self.subscription_list.remove(index));
+                    (sub, QuitOutcome::Removed(e))
+                }
+                // The `Subscription` was removed in the meantime
+                None => continue,
+            };
+            if let (Some(sub), Some(handler)) = (sub, &mut self.quit_handler) {
+                exclusive(handler)(&sub, &outcome);
+            }
+            if let QuitOutcome::Removed(e) = outcome {
+                self.unreported_error = Some(e);
+            }
+        }
+    }
+
+    /// Makes a single attempt to resubscribe the `Subscription` with the
+    /// given `creation_id`, updating its `creation_id` on success and
+    /// returning the new one. Returns `None` if there is no such
+    /// `Subscription`.
+    fn resubscribe(&mut self, creation_id: CreationID) -> Option<Result<CreationID>> {
+        let sub = self
+            .subscription_list
+            .iter()
+            .find(|s| s.creation_id == creation_id)?;
+        let (ship, app, path) = (sub.ship.clone(), sub.app.clone(), sub.path.clone());
+        let new_id = match self.subscribe(&ship, &app, &path) {
+            Ok(new_id) => new_id,
+            Err(e) => return Some(Err(e)),
+        };
+        let sub = self
+            .subscription_list
+            .iter_mut()
+            .find(|s| s.creation_id == creation_id);
+        match sub {
+            Some(sub) => sub.creation_id = new_id,
+            None => {
+                // The `Subscription` was removed while waiting for the ack,
+                // so nobody reads the new watch
+                let _ = self.send_unsubscribe(new_id);
+                return None;
+            }
+        }
+        if let Some(handler) = self.fact_handlers.remove(&creation_id) {
+            self.fact_handlers.insert(new_id, handler);
+        }
+        Some(Ok(new_id))
+    }
+
+    /// Blocks for up to `timeout` waiting for a single message from the
+    /// SSE connection and handles it, reopening the connection if it has
+    /// dropped. Returns early once acks or queued actions are due to be
+    /// sent, and sends them, or once a kicked `Subscription` is due to be
+    /// resubscribed.
+    fn receive_event(&mut self, timeout: Duration) -> Result<Received> {
+        self.reconnect_if_due();
+        let now = Instant::now();
+        let timeout = [
+            self.acks.due_in(&self.ack_policy),
+            self.batch.due_in(&self.batch_policy),
+            self.quit_subscriptions
+                .iter()
+                .map(|q| q.retry_at.saturating_duration_since(now))
+                .min(),
+        ]
+        .iter()
+        .flatten()
+        .fold(timeout, |timeout, due_in| std::cmp::min(timeout, *due_in));
+        let rec = self
+            .event_receiver
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner());
+        let received = match rec.recv_timeout(timeout) {
+            Ok(SseMessage::Opened) => {
+                if self.status.last_error.is_some() {
+                    self.status.reconnects += 1;
+                }
+                self.status.connected = true;
+                self.status.failed_attempts = 0;
+                self.connecting = false;
+                self.record_activity();
+                Ok(Received::ConnectionChange)
+            }
+            Ok(SseMessage::Heartbeat) => {
+                self.record_activity();
+                Ok(Received::Heartbeat)
+            }
+            Ok(SseMessage::Event(event)) => {
+                self.record_activity();
+                let (eid, event) = self.process_event(event);
+                Ok(Received::Event(eid, event))
+            }
+            Ok(SseMessage::Closed(e)) => {
+                self.schedule_reconnect(e);
+                Ok(Received::ConnectionChange)
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(Received::Nothing),
+            Err(RecvTimeoutError::Disconnected) => match self.reconnect_at {
+                // Wait for the reconnection to be due
+                Some(at) => {
+                    let wait = at.saturating_duration_since(Instant::now());
+                    thread::sleep(std::cmp::min(wait, timeout));
+                    Ok(Received::Nothing)
+                }
+                None => Err(UrbitAPIError::EventStreamClosed),
+            },
+        };
+        self.flush_due();
+        self.check_liveness();
+        received
+    }
+
+    /// Records that the SSE connection has dropped and, if the
+    /// `reconnect_policy` allows it, schedules it to be reopened. A session
+    /// which has been logged out, or rejected without a remembered code, is
+    /// never reopened, and its error is left for a `ChannelPump` to report.
+    fn schedule_reconnect(&mut self, e: UrbitAPIError) {
+        // Renew the session before reconnecting if the ship rejected it
+        let e = match e {
+            UrbitAPIError::Unauthorized if self.ship_interface.remembers_code() => {
+                match self.ship_interface.relogin() {
+                    Ok(()) => e,
+                    Err(relogin_err) => relogin_err,
+                }
+            }
+            e => e,
+        };
+        let retries = self.status.failed_attempts;
+        self.status.connected = false;
+        self.connecting = false;
+        self.status.last_error = Some(e.to_string());
+        // A logged out session can never reconnect, and neither can a
+        // rejected one without a remembered code to renew it with
+        let terminal = match e {
+            UrbitAPIError::LoggedOut => true,
+            UrbitAPIError::Unauthorized => !self.ship_interface.remembers_code(),
+            _ => false,
+        };
+        if terminal {
+            self.unreported_error = Some(e);
+            return;
+        }
+        // A delay too long to be added to the current time can never be
+        // waited out, so the connection is left closed
+        let reconnect_at = Instant::now().checked_add(self.reconnect_policy.delay(retries));
+        if let (true, Some(at)) = (self.reconnect_policy.should_retry(retries), reconnect_at) {
+            self.reconnect_at = Some(at);
+            self.status.failed_attempts += 1;
+        }
+    }
+
+    /// Reopens the SSE connection if it has dropped and the reconnection
+    /// is due, asking the ship to resend every event after the last one
+    /// which was received.
+    fn reconnect_if_due(&mut self) {
+        match self.reconnect_at {
+            Some(at) if Instant::now() >= at => {}
+            _ => return,
+        }
+        self.reconnect_at = None;
+        self.connecting = true;
+        let receiver = self
+            .ship_interface
+            .open_event_stream(&self.url, self.status.last_event_id);
+        *self
+            .event_receiver
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner()) = receiver;
+    }
+
+    /// Processes incoming SSE events until `check` returns `Some` or the
+    /// `timeout` passes, in which case `None` is returned. A `timeout` too
+    /// large to be added to the current time, such as `Duration::MAX`,
+    /// waits without a deadline.
+    fn wait_until<T>(
+        &mut self,
+        timeout: Duration,
+        mut check: impl FnMut(&mut Channel) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            if let Some(found) = check(self) {
+                return Ok(Some(found));
+            }
+            let wait = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    deadline - now
+                }
+                None => timeout,
+            };
+            self.receive_event(wait)?;
+        }
+    }
+
+    /// Waits up to `timeout` for the ship to respond to the message with
+    /// the given id. Returns the error trace of the app if it was nacked.
+    fn wait_for_ack(
+        &mut self,
+        id: u64,
+        timeout: Duration,
+    ) -> Result<std::result::Result<(), String>> {
+        self.pending_acks.insert(id, None);
+        let ack = self.wait_until(timeout, |channel| {
+            channel.pending_acks.get_mut(&id).and_then(|ack| ack.take())
+        });
+        self.pending_acks.remove(&id);
+        ack?.ok_or(UrbitAPIError::AckTimeout(id))
+    }
+
+    /// Parses a single SSE event into a `ChannelEvent` and processes it,
+    /// recording acks for pending messages, queueing kicked subscriptions
+    /// to be resubscribed, and moving facts into the proper `Subscription`.
+    /// Returns the `ChannelEvent` with the id of the SSE event.
+    fn process_event(&mut self, event: Event) -> (u64, ChannelEvent) {
+        // Eyre numbers every event. An event without an id is returned with
+        // id 0, which acks nothing.
+        let eid = event
+            .id
+            .as_ref()
+            .and_then(|eid| eid.parse().ok())
+            .unwrap_or(0);
+        if eid > 0 {
+            // Every event is acked, even ones which match no subscription,
+            // so that they do not pile up on the ship
+            self.acks.record(eid);
+            self.status.last_event_id = std::cmp::max(self.status.last_event_id, Some(eid));
+        }
+        let channel_event = ChannelEvent::parse(&event.data);
+        match &channel_event {
+            ChannelEvent::PokeAck { id, result } => {
+                if let Some(ack) = self.pending_acks.get_mut(id) {
+                    *ack = Some(result.clone());
+                }
+                if let Some(handler) = &mut self.poke_ack_handler {
+                    exclusive(handler)(*id, result);
+                }
+            }
+            ChannelEvent::WatchAck { id, result } => {
+                if let Some(ack) = self.pending_acks.get_mut(id) {
+                    *ack = Some(result.clone());
+                }
+                if let Some(index) = self.unacked_subscriptions.iter().position(|s| s == id) {
+                    self.unacked_subscriptions.remove(index);
+                    // A batched subscription which the app nacked is dead
+                    if let Err(trace) = result {
+                        self.subscription_list.retain(|s| s.creation_id != *id);
+                        self.fact_handlers.remove(id);
+                        self.unreported_error =
+                            Some(UrbitAPIError::SubscriptionNacked(trace.clone()));
+                    }
+                }
+            }
+            ChannelEvent::Quit { id } => {
+                let known = self.subscription_list.iter().any(|s| s.creation_id == *id);
+                let queued = self.quit_subscriptions.iter().any(|q| q.creation_id == *id);
+                if known && !queued {
+                    self.quit_subscriptions.push(QuitSubscription {
+                        creation_id: *id,
+                        retries: 0,
+                        retry_at: Instant::now(),
+                    });
+                }
+            }
+            ChannelEvent::Fact { id, payload, .. } => {
+                // Find which subscription this event is for.
+                let sub = self
+                    .subscription_list
+                    .iter_mut()
+                    .find(|s| s.creation_id == *id);
+                if let Some(sub) = sub {
+                    if !payload.is_null() {
+                        match self.fact_handlers.get_mut(id) {
+                            Some(handler) => exclusive(handler)(sub, &payload.dump()),
+                            None => sub.message_list.push(payload.dump()),
                         }
                     }
                 }
-                continue;
             }
-            break;
+            ChannelEvent::Error(_) => {}
         }
+        (eid, channel_event)
     }
 
-    /// Finds the first `Subscription` in the list which has a matching
-    /// `app` and `path`;
-    pub fn find_subscription(&mut self, app: &str, path: &str) -> Option<&mut Subscription> {
-        for sub in &mut self.subscription_list {
-            if sub.app == app && sub.path == path {
-                return Some(sub);
+    /// Sends the acks which the `ack_policy` says are due while events are
+    /// still being read, and the queued actions once they reach a threshold
+    /// of the `batch_policy`. Failures are recorded in the
+    /// `ConnectionStatus` and sent again later, so that reading events
+    /// carries on. `AckPolicy::All` otherwise leaves the acks to the end of
+    /// `parse_events`, so that the acks of events parsed together are sent
+    /// in one request.
+    fn flush_due(&mut self) {
+        if self.acks.is_due(&self.ack_policy) {
+            if let Err(e) = self.flush_acks() {
+                self.unreported_error = Some(e);
+            }
+        }
+        if let Err(e) = self.flush_if_due() {
+            self.unreported_error = Some(e);
+        }
+    }
+
+    /// Sends the ack of the highest unacked event if the `ack_policy` says
+    /// it is due. A failed ack is recorded in the `ConnectionStatus` and
+    /// retried with the next ack.
+    fn flush_acks(&mut self) -> Result<()> {
+        if let Some(eid) = self.acks.take_due(&self.ack_policy) {
+            if let Err(e) = self.ack(eid) {
+                self.acks.retry(eid);
+                return Err(e);
             }
         }
-        None
+        Ok(())
+    }
+
+    /// Acks the SSE event with the given id, and every event before it,
+    /// right away in its own request, leaving the queued actions queued.
+    /// This is how events are acked with `AckPolicy::Manual`, and the
+    /// `ack_policy` does not ack them again. A failed ack is recorded in
+    /// the `ConnectionStatus`.
+    pub fn ack(&mut self, event_id: u64) -> Result<()> {
+        let mut body = json::parse(r#"[]"#).unwrap();
+        body[0] = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "ack",
+            "event-id": event_id,
+        };
+        let sent = match self.ship_interface.send_put_request(&self.url, &body) {
+            Ok(resp) if resp.status().as_u16() == 204 => Ok(()),
+            Ok(_) => Err(UrbitAPIError::FailedToAck(event_id)),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            self.status.failed_acks += 1;
+            self.status.last_ack_error = Some(e.to_string());
+            return Err(e);
+        }
+        self.acks.acked(event_id);
+        Ok(())
+    }
+
+    /// Queues an ack for the SSE event with the given id, letting the ship
+    /// know that it does not need to be resent once the batch is flushed.
+    fn push_ack(&mut self, eid: u64) {
+        let action = object! {
+            "id": self.get_and_raise_message_id_count(),
+            "action": "ack",
+            "event-id": eid,
+        };
+        self.batch.push(action);
+    }
+
+    /// Finds the first `Subscription` in the list which has a matching
+    /// `app` and `path` on the ship of this channel
+    pub fn find_subscription(&mut self, app: &str, path: &str) -> Option<&mut Subscription> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.find_subscription_on_ship(&ship, app, path)
     }
 
     /// Finds the first `Subscription` in the list which has a matching
-    /// `app` and `path`, removes it from the list, and tells the ship
-    /// that you are unsubscribing. Returns `None` if failed to find
-    /// a subscription with a matching app & path.
-    pub fn unsubscribe(&mut self, app: &str, path: &str) -> Option<bool> {
+    /// `ship`, `app` and `path`
+    pub fn find_subscription_on_ship(
+        &mut self,
+        ship: &str,
+        app: &str,
+        path: &str,
+    ) -> Option<&mut Subscription> {
+        let ship = ship_without_sig(ship);
+        self.subscription_list
+            .iter_mut()
+            .find(|s| s.ship == ship && s.app == app && s.path == path)
+    }
+
+    /// Finds the first `Subscription` in the list which has a matching
+    /// `app` and `path` on the ship of this channel, tells the ship that
+    /// you are unsubscribing, and removes it from the list. Returns
+    /// `UrbitAPIError::SubscriptionNotFound` if failed to find a
+    /// subscription with a matching app & path.
+    pub fn unsubscribe(&mut self, app: &str, path: &str) -> Result<()> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.unsubscribe_on_ship(&ship, app, path)
+    }
+
+    /// Unsubscribes from the `Subscription` with a matching `ship`, `app`
+    /// and `path` like `unsubscribe`.
+    pub fn unsubscribe_on_ship(&mut self, ship: &str, app: &str, path: &str) -> Result<()> {
+        let ship = ship_without_sig(ship);
         let index = self
             .subscription_list
             .iter()
-            .position(|s| s.app == app && s.path == path)?;
-        self.subscription_list.remove(index);
-        Some(true)
+            .position(|s| s.ship == ship && s.app == app && s.path == path)
+            .ok_or_else(|| {
+                UrbitAPIError::SubscriptionNotFound(app.to_string(), path.to_string())
+            })?;
+        self.send_unsubscribe(self.subscription_list[index].creation_id)?;
+
+        let sub = self.subscription_list.remove(index);
+        self.fact_handlers.remove(&sub.creation_id);
+        Ok(())
     }
 
-    /// Deletes the channel
-    pub fn delete_channel(self) {
-        let mut json = json::parse(r#"[]"#).unwrap();
-        json[0] = object! {
-            "id": self.message_id_count,
+    /// Queues a poke to be sent with the next flush of the batch of
+    /// actions, returning its message id
+    pub fn queue_poke(&mut self, app: &str, mark: &str, json: &str) -> Result<u64> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.queue_poke_on_ship(&ship, app, mark, json)
+    }
+
+    /// Queues a poke to an app on the given ship like `queue_poke`
+    pub fn queue_poke_on_ship(
+        &mut self,
+        ship: &str,
+        app: &str,
+        mark: &str,
+        json: &str,
+    ) -> Result<u64> {
+        let id = self.get_and_raise_message_id_count();
+        self.batch.push(object! {
+                "id": id,
+                "action": "poke",
+                "ship": ship_without_sig(ship),
+                "app": app,
+                "mark": mark,
+                "json": json,
+        });
+        self.flush_if_due()?;
+        Ok(id)
+    }
+
+    /// Queues a subscription to the provided app/path to be sent with the
+    /// next flush of the batch of actions. The `Subscription` is added to
+    /// the `subscription_list` once the batch has been sent, without
+    /// waiting for the app to ack it. If the app later nacks it, it is
+    /// removed again and `UrbitAPIError::SubscriptionNacked` is reported
+    /// by a `ChannelPump`.
+    pub fn queue_subscription(&mut self, app: &str, path: &str) -> Result<CreationID> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.queue_subscription_on_ship(&ship, app, path)
+    }
+
+    /// Queues a subscription to an app on the given ship like
+    /// `queue_subscription`
+    pub fn queue_subscription_on_ship(
+        &mut self,
+        ship: &str,
+        app: &str,
+        path: &str,
+    ) -> Result<CreationID> {
+        let creation_id = self.get_and_raise_message_id_count();
+        self.batch.push(object! {
+                "id": creation_id,
+                "action": "subscribe",
+                "ship": ship_without_sig(ship),
+                "app": app,
+                "path": path,
+        });
+        self.batch.subscriptions.push(Subscription {
+            channel_uid: self.uid.clone(),
+            creation_id,
+            ship: ship_without_sig(ship),
+            app: app.to_string(),
+            path: path.to_string(),
+            message_list: vec![],
+        });
+        self.flush_if_due()?;
+        Ok(creation_id)
+    }
+
+    /// Queues an unsubscribe from the `Subscription` with a matching `app`
+    /// and `path` on the ship of this channel, which is removed from the
+    /// `subscription_list` once the batch has been sent.
+    pub fn queue_unsubscribe(&mut self, app: &str, path: &str) -> Result<()> {
+        let ship = self.ship_interface.ship_name.clone();
+        self.queue_unsubscribe_on_ship(&ship, app, path)
+    }
+
+    /// Queues an unsubscribe from the `Subscription` with a matching
+    /// `ship`, `app` and `path` like `queue_unsubscribe`
+    pub fn queue_unsubscribe_on_ship(&mut self, ship: &str, app: &str, path: &str) -> Result<()> {
+        let ship = ship_without_sig(ship);
+        let creation_id = self
+            .subscription_list
+            .iter()
+            .find(|s| s.ship == ship && s.app == app && s.path == path)
+            .map(|s| s.creation_id)
+            .ok_or_else(|| {
+                UrbitAPIError::SubscriptionNotFound(app.to_string(), path.to_string())
+            })?;
+
+        let id = self.get_and_raise_message_id_count();
+        self.batch.push(object! {
+            "id": id,
+            "action": "unsubscribe",
+            "subscription": creation_id,
+        });
+        self.batch.unsubscriptions.push(creation_id);
+        self.flush_if_due()
+    }
+
+    /// Queues an ack for the SSE event with the given id to be sent with
+    /// the next flush of the batch of actions
+    pub fn queue_ack(&mut self, event_id: u64) -> Result<()> {
+        self.push_ack(event_id);
+        self.flush_if_due()
+    }
+
+    /// The number of actions waiting in the batch to be flushed
+    pub fn queued_actions(&self) -> usize {
+        self.batch.len()
+    }
+
+    /// Sends every queued action to the ship in a single PUT, and applies
+    /// their changes to the `subscription_list`. If the PUT fails, the
+    /// error is returned and recorded in the `ConnectionStatus`, and the
+    /// actions stay queued to be sent again, unless they are discarded
+    /// with `discard_queued_actions`.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let sent = match self
+            .ship_interface
+            .send_put_request(&self.url, &batch.to_json())
+        {
+            Ok(resp) if resp.status().as_u16() == 204 => Ok(()),
+            Ok(_) => Err(UrbitAPIError::FailedToSendBatch(batch.len())),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            self.status.failed_batches += 1;
+            self.status.last_batch_error = Some(e.to_string());
+            self.batch = batch;
+            self.batch.restart_delay();
+            return Err(e);
+        }
+
+        self.subscription_list
+            .retain(|s| !batch.unsubscriptions.contains(&s.creation_id));
+        for creation_id in &batch.unsubscriptions {
+            self.fact_handlers.remove(creation_id);
+        }
+        self.unacked_subscriptions
+            .extend(batch.subscriptions.iter().map(|s| s.creation_id));
+        self.subscription_list.extend(batch.subscriptions);
+        Ok(())
+    }
+
+    /// Discards every queued action without sending it, such as a batch
+    /// which the ship keeps rejecting, and returns how many were queued
+    pub fn discard_queued_actions(&mut self) -> usize {
+        std::mem::take(&mut self.batch).len()
+    }
+
+    /// Flushes the queued actions if they have reached one of the
+    /// thresholds of the `batch_policy`
+    fn flush_if_due(&mut self) -> Result<()> {
+        if self.batch.is_due(&self.batch_policy) {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    /// Deletes the channel on the ship
+    pub fn delete_channel(mut self) -> Result<()> {
+        self.send_delete(false)
+    }
+
+    /// Deletes the channel on the ship in a single PUT, first unsubscribing
+    /// from every `Subscription` in the `subscription_list` if
+    /// `unsubscribe` is set.
+    fn send_delete(&mut self, unsubscribe: bool) -> Result<()> {
+        let mut batch = ActionBatch::default();
+        if unsubscribe {
+            let creation_ids: Vec<CreationID> = self
+                .subscription_list
+                .iter()
+                .map(|s| s.creation_id)
+                .collect();
+            for creation_id in creation_ids {
+                batch.push(object! {
+                    "id": self.get_and_raise_message_id_count(),
+                    "action": "unsubscribe",
+                    "subscription": creation_id,
+                });
+            }
+        }
+        batch.push(object! {
+            "id": self.get_and_raise_message_id_count(),
             "action": "delete",
+        });
+
+        let resp = self
+            .ship_interface
+            .send_put_request(&self.url, &batch.to_json())?;
+        if resp.status().as_u16() != 204 {
+            return Err(UrbitAPIError::FailedToDeleteChannel);
+        }
+        self.registration.mark_deleted();
+        self.subscription_list.clear();
+        self.fact_handlers.clear();
+        self.ship_interface.unregister_channel(&self.url);
+        Ok(())
+    }
+}
+
+impl Drop for Channel {
+    /// Deletes the channel on the ship, after sending the queued actions
+    /// and unsubscribing from every `Subscription`, if `delete_on_drop` is
+    /// set and the channel has not already been deleted
+    fn drop(&mut self) {
+        let deleted = self.registration.is_deleted();
+        if self.delete_on_drop && !deleted && !self.ship_interface.is_logged_out() {
+            // Nothing can be reported from a drop, and the channel is
+            // deleted even if the queued actions fail to send
+            let _ = self.flush();
+            let _ = self.send_delete(true);
+        }
+    }
+}
+
+/// Strips the leading `~` from a ship name, as eyre expects ship names
+/// without it
+pub(crate) fn ship_without_sig(ship: &str) -> String {
+    ship.trim_start_matches('~').to_string()
+}
+
+/// Reaches the value of a `Mutex` held by a `Channel`, which is only ever
+/// used through `&mut Channel` and so is never contended
+fn exclusive<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_server, respond, EVENT_STREAM, NO_CONTENT};
+    use std::io::Read;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::sync::Arc;
+
+    // Builds a `Channel` for the ship at `url` without contacting it
+    fn offline_channel(url: &str) -> Channel {
+        let ship_interface =
+            ShipInterface::from_session_auth(url, "urbauth-~zod=0v5.abcde", None).unwrap();
+        let channel_url = format!("{}/~/channel/test", url);
+        let receiver = sse::closed(UrbitAPIError::EventStreamClosed);
+        Channel::from_parts(
+            ship_interface,
+            "test".to_string(),
+            channel_url,
+            vec![],
+            receiver,
+        )
+    }
+
+    // Builds a `Channel` for the ship at `url` whose SSE connection is open
+    // and reads the messages sent with the returned `Sender`
+    fn fed_channel(url: &str) -> (Channel, Sender<SseMessage>) {
+        let mut channel = offline_channel(url);
+        let (sender, _, receiver) = sse::channel();
+        channel.event_receiver = Mutex::new(receiver);
+        sender.send(SseMessage::Opened).unwrap();
+        (channel, sender)
+    }
+
+    // An SSE event with the given id and data
+    fn event(eid: u64, data: &str) -> SseMessage {
+        SseMessage::Event(Event {
+            id: Some(eid.to_string()),
+            event_type: None,
+            data: data.to_string(),
+        })
+    }
+
+    // Starts a fake ship which answers every request with a 204, and sends
+    // the json body of every PUT to the returned `Receiver`
+    fn fake_ship() -> (String, Receiver<JsonValue>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        let url = fake_server(move |request, stream| {
+            if let (true, Some(json)) = (request.is("PUT", "/"), request.json()) {
+                let _ = sender.lock().unwrap().send(json);
+            }
+            respond(stream, NO_CONTENT)
+        });
+        (url, receiver)
+    }
+
+    // Waits for the fake ship to receive an action of the given kind, and
+    // returns it
+    fn next_action(puts: &Receiver<JsonValue>, action: &str) -> JsonValue {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let put = puts.recv_timeout(wait).expect("no such action was sent");
+            if let Some(found) = put.members().find(|a| a["action"] == action) {
+                return found.clone();
+            }
+        }
+    }
+
+    // Answers every subscribe the fake ship receives with a watch ack, or
+    // with a nack if `nack` is set
+    fn answer_subscribes(puts: Receiver<JsonValue>, sender: Sender<SseMessage>, nack: bool) {
+        thread::spawn(move || {
+            let mut eid = 100;
+            for put in puts {
+                for action in put.members().filter(|a| a["action"] == "subscribe") {
+                    let result = if nack {
+                        r#""err": "trace""#
+                    } else {
+                        r#""ok": "ok""#
+                    };
+                    let data = format!(
+                        r#"{{"id": {}, "response": "subscribe", {}}}"#,
+                        action["id"], result
+                    );
+                    if sender.send(event(eid, &data)).is_err() {
+                        return;
+                    }
+                    eid += 1;
+                }
+            }
+        });
+    }
+
+    // Adds a `Subscription` to the list of a channel without contacting
+    // the ship
+    fn add_subscription(channel: &mut Channel, creation_id: CreationID, path: &str) {
+        channel.subscription_list.push(Subscription {
+            channel_uid: channel.uid.clone(),
+            creation_id,
+            ship: "zod".to_string(),
+            app: "app".to_string(),
+            path: path.to_string(),
+            message_list: vec![],
+        });
+    }
+
+    #[test]
+    // Verify that a dropped SSE connection is reopened until the
+    // `reconnect_policy` gives up
+    fn schedules_reconnects() {
+        // Nothing listens on the port, so every connection fails
+        let mut channel = offline_channel("http://127.0.0.1:1");
+        channel.reconnect_policy = RetryPolicy {
+            max_retries: Some(1),
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+        };
+        channel.receive_event(Duration::from_secs(1)).unwrap();
+        assert_eq!(channel.connection_state(), ConnectionState::Connecting);
+        assert_eq!(channel.connection_status().failed_attempts, 1);
+        assert!(channel.connection_status().last_error.is_some());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while channel.connection_state() != ConnectionState::Closed {
+            assert!(Instant::now() < deadline);
+            channel.receive_event(Duration::from_millis(100)).unwrap();
+        }
+        assert!(matches!(
+            channel.receive_event(Duration::from_millis(10)),
+            Err(UrbitAPIError::EventStreamClosed)
+        ));
+    }
+
+    #[test]
+    // Verify that a reconnection delay too long to be scheduled leaves the
+    // connection closed instead of overflowing
+    fn does_not_overflow_reconnect_delay() {
+        let mut channel = offline_channel("http://127.0.0.1:1");
+        channel.reconnect_policy = RetryPolicy {
+            max_retries: None,
+            initial_delay: Duration::MAX,
+            max_delay: Duration::MAX,
         };
-        let res = self.ship_interface.send_put_request(&self.url, &json);
-        std::mem::drop(self);
+        channel.receive_event(Duration::from_secs(1)).unwrap();
+        assert_eq!(channel.connection_state(), ConnectionState::Closed);
+        assert_eq!(channel.connection_status().failed_attempts, 0);
+    }
+
+    #[test]
+    // Verify that a reopened SSE connection sends the id of the last
+    // received event as the `Last-Event-ID`
+    fn reconnects_with_last_event_id() {
+        let (sender, last_event_ids) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        let url = fake_server(move |request, stream| {
+            let last_event_id = request.header("last-event-id").map(str::to_string);
+            let _ = sender.lock().unwrap().send(last_event_id);
+            let event = "id: 6\ndata: {\"id\": 3, \"response\": \"quit\"}\n\n";
+            respond(stream, &format!("{}{}", EVENT_STREAM, event));
+            false
+        });
+
+        let mut channel = offline_channel(&url);
+        channel.ack_policy = AckPolicy::Manual;
+        channel.status.last_event_id = Some(5);
+        channel.reconnect_policy.initial_delay = Duration::from_millis(0);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            assert!(Instant::now() < deadline);
+            if let Received::Event(eid, event) =
+                channel.receive_event(Duration::from_millis(100)).unwrap()
+            {
+                assert_eq!((eid, event), (6, ChannelEvent::Quit { id: 3 }));
+                break;
+            }
+        }
+        let last_event_id = last_event_ids.recv_timeout(Duration::from_secs(1));
+        assert_eq!(last_event_id.unwrap().as_deref(), Some("5"));
+        // Events are resent after the last one received, even if it has not
+        // been acked yet
+        assert_eq!(channel.connection_status().last_event_id, Some(6));
+    }
+
+    #[test]
+    // Verify that the pump thread reports its errors and stops once the
+    // SSE connection has closed for good
+    fn pump_reports_errors() {
+        let mut channel = offline_channel("http://127.0.0.1:1");
+        channel.reconnect_policy = RetryPolicy {
+            max_retries: Some(1),
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_millis(200),
+        };
+        let pump = channel.start_pump();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pump.on_error(move |e| {
+            let _ = sender.send(e.to_string());
+        });
+        let error = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(error, UrbitAPIError::EventStreamClosed.to_string());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pump.is_running() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let channel = pump.shutdown();
+        assert_eq!(channel.connection_state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    // Verify that an SSE connection rejected for its session is not
+    // reopened without a remembered code, and that the pump reports it and
+    // stops
+    fn pump_stops_on_rejected_session() {
+        let (channel, sender) = fed_channel("http://127.0.0.1:1");
+        let pump = channel.start_pump();
+        let (errors, received) = std::sync::mpsc::channel();
+        pump.on_error(move |e| {
+            let _ = errors.send(e.to_string());
+        });
+        sender
+            .send(SseMessage::Closed(UrbitAPIError::Unauthorized))
+            .unwrap();
+        drop(sender);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pump.is_running() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let channel = pump.shutdown();
+        assert_eq!(
+            received.try_iter().collect::<Vec<_>>(),
+            vec![
+                UrbitAPIError::Unauthorized.to_string(),
+                UrbitAPIError::EventStreamClosed.to_string()
+            ]
+        );
+        assert_eq!(channel.connection_state(), ConnectionState::Closed);
+        assert_eq!(channel.connection_status().failed_attempts, 0);
+    }
+
+    #[test]
+    // Verify that the pump thread acks events with `AckPolicy::All` while
+    // they keep arriving, without waiting for the connection to go idle
+    fn pump_acks_streamed_events() {
+        let (url, puts) = fake_ship();
+        let (channel, sender) = fed_channel(&url);
+        let streaming = Arc::new(AtomicBool::new(true));
+        let feeder = {
+            let streaming = streaming.clone();
+            thread::spawn(move || {
+                let mut eid = 1;
+                while streaming.load(Ordering::SeqCst) {
+                    let data = format!(
+                        "{{\"id\": {}, \"response\": \"poke\", \"ok\": \"ok\"}}",
+                        eid
+                    );
+                    if sender.send(event(eid, &data)).is_err() {
+                        return;
+                    }
+                    eid += 1;
+                    thread::sleep(Duration::from_millis(2));
+                }
+            })
+        };
+        let pump = channel.start_pump();
+        let ack = next_action(&puts, "ack");
+        assert!(streaming.load(Ordering::SeqCst));
+        assert!(ack["event-id"].as_u64().unwrap() > 0);
+        streaming.store(false, Ordering::SeqCst);
+        feeder.join().unwrap();
+        pump.shutdown();
+    }
+
+    #[test]
+    // Verify that waiting for a message on a quiet subscription acks the
+    // events of busy subscriptions before the wait ends
+    fn acks_while_waiting_for_message() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        add_subscription(&mut channel, 3, "/quiet");
+        add_subscription(&mut channel, 4, "/busy");
+        for eid in 1..=100 {
+            let data = r#"{"id": 4, "response": "diff", "json": "busy"}"#;
+            sender.send(event(eid, data)).unwrap();
+        }
+        let waiter = thread::spawn(move || {
+            let message = channel.next_message("app", "/quiet", Duration::from_secs(30));
+            (channel, message)
+        });
+        next_action(&puts, "ack");
+        let data = r#"{"id": 3, "response": "diff", "json": "quiet"}"#;
+        sender.send(event(101, data)).unwrap();
+        let (channel, message) = waiter.join().unwrap();
+        assert_eq!(message.unwrap(), Some("\"quiet\"".to_string()));
+        assert_eq!(channel.subscription_list[1].message_list.len(), 100);
+    }
+
+    #[test]
+    // Verify that a nacked poke returns the error trace of the app, and
+    // that waiting forever does not overflow the deadline
+    fn returns_poke_nack() {
+        let (url, _puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        let id = channel.message_id_count;
+        let data = format!(r#"{{"id": {}, "response": "poke", "err": "trace"}}"#, id);
+        sender.send(event(1, &data)).unwrap();
+        match channel.poke_and_wait("hood", "helm-hi", "nacked", Duration::MAX) {
+            Err(UrbitAPIError::PokeNacked(trace)) => assert_eq!(trace, "trace"),
+            res => panic!("Unexpected poke result: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that a batched subscription is removed once its app nacks it,
+    // and kept once its app acks it
+    fn removes_nacked_batched_subscription() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        let nacked = channel.queue_subscription("app", "/nacked").unwrap();
+        let acked = channel.queue_subscription("app", "/acked").unwrap();
+        channel.flush().unwrap();
+        next_action(&puts, "subscribe");
+        assert_eq!(channel.subscription_list.len(), 2);
+
+        let nack = format!(
+            r#"{{"id": {}, "response": "subscribe", "err": "trace"}}"#,
+            nacked
+        );
+        let ack = format!(
+            r#"{{"id": {}, "response": "subscribe", "ok": "ok"}}"#,
+            acked
+        );
+        sender.send(event(1, &nack)).unwrap();
+        sender.send(event(2, &ack)).unwrap();
+        channel.parse_events();
+        assert!(channel.find_subscription("app", "/nacked").is_none());
+        assert!(channel.find_subscription("app", "/acked").is_some());
+        assert!(channel.unacked_subscriptions.is_empty());
+        match channel.unreported_error.take() {
+            Some(UrbitAPIError::SubscriptionNacked(trace)) => assert_eq!(trace, "trace"),
+            res => panic!("Unexpected error: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that a connection which goes silent is reported stale once,
+    // and closed and reopened if `reconnect_when_stale` is set
+    fn detects_stale_connection() {
+        // A server which opens every event stream and then sends nothing,
+        // reporting once the channel has closed it
+        let (closed_sender, closed) = std::sync::mpsc::channel();
+        let closed_sender = Mutex::new(closed_sender);
+        let url = fake_server(move |_, stream| {
+            respond(stream, EVENT_STREAM);
+            let _ = stream.read(&mut [0; 1]);
+            let _ = closed_sender.lock().unwrap().send(());
+            false
+        });
+        // Opens a channel to the server which goes stale after a few ms,
+        // counting how often it is reported stale
+        let open_channel = |reconnect_when_stale: bool| {
+            let mut channel = offline_channel(&url);
+            channel.reconnect_policy.initial_delay = Duration::from_millis(0);
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while channel.connection_state() != ConnectionState::Open {
+                assert!(Instant::now() < deadline);
+                channel.receive_event(Duration::from_millis(10)).unwrap();
+            }
+            channel.reconnect_policy.initial_delay = Duration::from_secs(60);
+            channel.stale_after = Some(Duration::from_millis(20));
+            channel.reconnect_when_stale = reconnect_when_stale;
+            let reports = Arc::new(Mutex::new(0));
+            let counter = reports.clone();
+            channel.on_stale(move |_| *counter.lock().unwrap() += 1);
+            (channel, reports)
+        };
+
+        let (mut channel, reports) = open_channel(false);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while *reports.lock().unwrap() == 0 {
+            assert!(Instant::now() < deadline);
+            channel.receive_event(Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(channel.connection_state(), ConnectionState::Stale);
+        for _ in 0..5 {
+            channel.receive_event(Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(*reports.lock().unwrap(), 1);
+        assert_eq!(channel.connection_state(), ConnectionState::Stale);
+
+        let (mut channel, reports) = open_channel(true);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while *reports.lock().unwrap() == 0 {
+            assert!(Instant::now() < deadline);
+            channel.receive_event(Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(channel.connection_state(), ConnectionState::Connecting);
+        assert!(channel.reconnect_at.is_some());
+        assert_eq!(
+            channel.connection_status().last_error,
+            Some(UrbitAPIError::ConnectionStale.to_string())
+        );
+        assert_eq!(*reports.lock().unwrap(), 1);
+        // The stale connection is closed rather than left to its thread
+        assert!(closed.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    // Verify that the same app and path subscribed on two ships are kept
+    // as two `Subscription`s, which are found and ended by their ship
+    // whether or not it is written with its `~`
+    fn keeps_subscriptions_per_ship() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        answer_subscribes(puts, sender, false);
+        for ship in &["~zod", "~bus"] {
+            channel
+                .create_new_subscription_on_ship(ship, "app", "/path")
+                .unwrap();
+        }
+        let ships: Vec<&str> = channel
+            .subscription_list
+            .iter()
+            .map(|s| s.ship.as_str())
+            .collect();
+        assert_eq!(ships, vec!["zod", "bus"]);
+
+        let (url, puts) = fake_ship();
+        let (mut channel, _sender) = fed_channel(&url);
+        add_subscription(&mut channel, 1, "/path");
+        add_subscription(&mut channel, 2, "/path");
+        channel.subscription_list[1].ship = "bus".to_string();
+
+        let found = |channel: &mut Channel, ship: &str| {
+            channel
+                .find_subscription_on_ship(ship, "app", "/path")
+                .map(|sub| sub.creation_id)
+        };
+        assert_eq!(found(&mut channel, "zod"), Some(1));
+        assert_eq!(found(&mut channel, "~zod"), Some(1));
+        assert_eq!(found(&mut channel, "bus"), Some(2));
+        assert_eq!(found(&mut channel, "~bus"), Some(2));
+        assert_eq!(found(&mut channel, "~nec"), None);
+
+        channel.unsubscribe_on_ship("~bus", "app", "/path").unwrap();
+        assert_eq!(next_action(&puts, "unsubscribe")["subscription"], 2);
+        assert_eq!(found(&mut channel, "bus"), None);
+        assert_eq!(
+            channel
+                .find_subscription("app", "/path")
+                .map(|s| s.creation_id),
+            Some(1)
+        );
+        match channel.unsubscribe_on_ship("bus", "app", "/path") {
+            Err(UrbitAPIError::SubscriptionNotFound(_, _)) => {}
+            res => panic!("Unexpected unsubscribe result: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that a channel dropped with `delete_on_drop` set sends its
+    // queued actions, and then unsubscribes from every `Subscription`
+    // before deleting the channel in one PUT
+    fn deletes_channel_on_drop() {
+        let (url, puts) = fake_ship();
+        let (mut channel, _sender) = fed_channel(&url);
+        add_subscription(&mut channel, 1, "/first");
+        add_subscription(&mut channel, 5, "/second");
+        channel.queue_poke("app", "mark", "\"queued\"").unwrap();
+        channel.delete_on_drop = true;
+        drop(channel);
+
+        let timeout = Duration::from_secs(10);
+        let flushed = puts.recv_timeout(timeout).unwrap();
+        assert_eq!(flushed[0]["action"], "poke");
+        let deleted = puts.recv_timeout(timeout).unwrap();
+        let actions: Vec<(String, Option<u64>)> = deleted
+            .members()
+            .map(|a| (a["action"].to_string(), a["subscription"].as_u64()))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                ("unsubscribe".to_string(), Some(1)),
+                ("unsubscribe".to_string(), Some(5)),
+                ("delete".to_string(), None),
+            ]
+        );
+        assert!(puts.try_recv().is_err());
+    }
+
+    #[test]
+    // Verify that handlers only need to be `Send`, so that they can keep
+    // their own state in a `Cell`
+    fn accepts_handlers_which_are_not_sync() {
+        let (mut channel, sender) = fed_channel("http://127.0.0.1:1");
+        let (counts, received) = std::sync::mpsc::channel();
+        let acks = std::cell::Cell::new(0);
+        channel.on_poke_ack(move |_, _| {
+            acks.set(acks.get() + 1);
+            let _ = counts.send(acks.get());
+        });
+        sender
+            .send(event(1, r#"{"id": 2, "response": "poke", "ok": "ok"}"#))
+            .unwrap();
+        sender
+            .send(event(2, r#"{"id": 3, "response": "poke", "ok": "ok"}"#))
+            .unwrap();
+        channel.parse_events();
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    // Verify that a kicked subscription is resubscribed, and that its fact
+    // handler follows it to its new `creation_id`
+    fn resubscribes_kicked_subscription() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        answer_subscribes(puts, sender.clone(), false);
+        add_subscription(&mut channel, 1, "/path");
+        let (facts, received) = std::sync::mpsc::channel();
+        channel.on_fact(1, move |_, fact| {
+            let _ = facts.send(fact.to_string());
+        });
+        let outcomes = Arc::new(Mutex::new(vec![]));
+        let recorded = outcomes.clone();
+        channel.on_quit(move |sub, outcome| {
+            let resubscribed = matches!(outcome, QuitOutcome::Resubscribed);
+            recorded
+                .lock()
+                .unwrap()
+                .push((sub.creation_id, resubscribed));
+        });
+
+        sender
+            .send(event(1, r#"{"id": 1, "response": "quit"}"#))
+            .unwrap();
+        channel.parse_events();
+        let new_id = channel.subscription_list[0].creation_id;
+        assert_ne!(new_id, 1);
+        assert_eq!(*outcomes.lock().unwrap(), vec![(new_id, true)]);
+
+        let fact = format!(
+            r#"{{"id": {}, "response": "diff", "json": "fact"}}"#,
+            new_id
+        );
+        sender.send(event(2, &fact)).unwrap();
+        channel.parse_events();
+        let fact = received.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(fact, "\"fact\"");
+    }
+
+    #[test]
+    // Verify that a kicked subscription which cannot be resubscribed is
+    // retried without blocking, and removed once the `resubscribe_policy`
+    // gives up
+    fn removes_kicked_subscription() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        answer_subscribes(puts, sender.clone(), true);
+        channel.resubscribe_policy = RetryPolicy {
+            max_retries: Some(1),
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_millis(200),
+        };
+        add_subscription(&mut channel, 1, "/path");
+        channel.on_fact(1, |_, _| {});
+        let outcomes = Arc::new(Mutex::new(vec![]));
+        let recorded = outcomes.clone();
+        channel.on_quit(move |sub, outcome| {
+            let nacked = matches!(
+                outcome,
+                QuitOutcome::Removed(UrbitAPIError::SubscriptionNacked(_))
+            );
+            recorded.lock().unwrap().push((sub.path.clone(), nacked));
+        });
+
+        sender
+            .send(event(1, r#"{"id": 1, "response": "quit"}"#))
+            .unwrap();
+        let started = Instant::now();
+        channel.parse_events();
+        // The retry is left for later instead of waiting for it
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert_eq!(channel.subscription_list.len(), 1);
+        assert!(outcomes.lock().unwrap().is_empty());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while outcomes.lock().unwrap().is_empty() {
+            assert!(Instant::now() < deadline);
+            channel.receive_event(Duration::from_millis(50)).unwrap();
+            channel.parse_events();
+        }
+        assert_eq!(*outcomes.lock().unwrap(), vec![("/path".to_string(), true)]);
+        assert!(channel.subscription_list.is_empty());
+        assert!(channel.fact_handlers.is_empty());
+        assert!(matches!(
+            channel.unreported_error,
+            Some(UrbitAPIError::SubscriptionNacked(_))
+        ));
+    }
+
+    #[test]
+    // Verify that a nacked subscription is not added to the list
+    fn rejects_nacked_subscription() {
+        let (url, puts) = fake_ship();
+        let (mut channel, sender) = fed_channel(&url);
+        answer_subscribes(puts, sender, true);
+        match channel.create_new_subscription("app", "/path") {
+            Err(UrbitAPIError::SubscriptionNacked(trace)) => assert_eq!(trace, "trace"),
+            res => panic!("Unexpected subscribe result: {:?}", res),
+        }
+        assert!(channel.subscription_list.is_empty());
+    }
+
+    #[test]
+    // Verify that a subscription which is never acked is ended on the ship
+    // and not added to the list
+    fn ends_unacked_subscription() {
+        let (url, puts) = fake_ship();
+        let (mut channel, _sender) = fed_channel(&url);
+        channel.ack_timeout = Duration::from_millis(100);
+        let creation_id = channel.message_id_count;
+        match channel.create_new_subscription("app", "/path") {
+            Err(UrbitAPIError::AckTimeout(id)) => assert_eq!(id, creation_id),
+            res => panic!("Unexpected subscribe result: {:?}", res),
+        }
+        assert!(channel.subscription_list.is_empty());
+        assert_eq!(
+            next_action(&puts, "unsubscribe")["subscription"],
+            creation_id
+        );
+    }
+
+    #[test]
+    // Verify that logging out deletes every open channel with a fresh
+    // message id, and marks their handles as deleted
+    fn logout_deletes_channels() {
+        let (url, puts) = fake_ship();
+        let ship_interface =
+            ShipInterface::from_session_auth(&url, "urbauth-~zod=0v5.abcde", None).unwrap();
+        let mut first = ship_interface.create_channel().unwrap();
+        let second = ship_interface.create_channel().unwrap();
+        first.poke("hood", "helm-hi", "poke").unwrap();
+        let expected = vec![first.message_id_count, second.message_id_count];
+        ship_interface.logout().unwrap();
+        assert!(first.registration.is_deleted());
+        assert!(second.registration.is_deleted());
+
+        let deletes: Vec<u64> = puts
+            .try_iter()
+            .flat_map(|put| put.members().cloned().collect::<Vec<_>>())
+            .filter(|a| a["action"] == "delete")
+            .filter_map(|a| a["id"].as_u64())
+            .collect();
+        assert_eq!(deletes, expected);
     }
 }
@@ -0,0 +1,166 @@
+use crate::error::{Result, UrbitAPIError};
+use reqwest::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use std::time::{Duration, SystemTime};
+
+// The prefix of the name of the session auth cookie set by a ship
+const COOKIE_PREFIX: &str = "urbauth-~";
+
+/// The session auth cookie which a ship sets on login
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionCookie {
+    /// The name of the cookie, such as `urbauth-~zod`
+    pub name: String,
+    /// The value of the cookie
+    pub value: String,
+    /// The name of the ship, without the `~`
+    pub ship_name: String,
+    /// The `Domain` attribute of the cookie, if set
+    pub domain: Option<String>,
+    /// The `Path` attribute of the cookie, if set
+    pub path: Option<String>,
+    /// When the session expires, if known
+    pub expires_at: Option<SystemTime>,
+}
+
+impl SessionCookie {
+    /// Parses a `Set-Cookie` header value, or a bare `name=value` pair,
+    /// into a `SessionCookie`. `now` is when the header was received, and
+    /// is used to resolve the `Max-Age` attribute, which takes precedence
+    /// over `Expires`.
+    pub fn parse(set_cookie: &str, now: SystemTime) -> Result<SessionCookie> {
+        let malformed = || UrbitAPIError::MalformedSessionCookie(set_cookie.to_string());
+        let mut attributes = set_cookie.split(';');
+
+        // Split the leading `name=value` pair
+        let mut pair = attributes.next().ok_or_else(malformed)?.splitn(2, '=');
+        let name = pair.next().ok_or_else(malformed)?.trim();
+        let value = pair.next().ok_or_else(malformed)?.trim();
+        if !name.starts_with(COOKIE_PREFIX) || value.is_empty() {
+            return Err(malformed());
+        }
+        let ship_name = &name[COOKIE_PREFIX.len()..];
+        if !is_valid_ship_name(ship_name) {
+            return Err(malformed());
+        }
+
+        let mut cookie = SessionCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            ship_name: ship_name.to_string(),
+            domain: None,
+            path: None,
+            expires_at: None,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let mut parts = attribute.splitn(2, '=');
+            let attr_name = parts.next().unwrap_or("").trim().to_lowercase();
+            let attr_value = parts.next().unwrap_or("").trim();
+            match attr_name.as_str() {
+                "domain" => cookie.domain = Some(attr_value.to_string()),
+                "path" => cookie.path = Some(attr_value.to_string()),
+                "max-age" => max_age = attr_value.parse::<u64>().ok(),
+                "expires" => cookie.expires_at = httpdate::parse_http_date(attr_value).ok(),
+                _ => {}
+            }
+        }
+        if let Some(secs) = max_age {
+            cookie.expires_at = Some(now + Duration::from_secs(secs));
+        }
+        Ok(cookie)
+    }
+
+    /// Finds and parses the session auth cookie among the `Set-Cookie`
+    /// headers of a login response. Fails with `FailedToLogin` if there is
+    /// no session auth cookie.
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Result<SessionCookie> {
+        let set_cookie = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.trim_start().starts_with(COOKIE_PREFIX))
+            .ok_or(UrbitAPIError::FailedToLogin)?;
+        SessionCookie::parse(set_cookie, now)
+    }
+
+    /// The `name=value` pair sent back to the ship in the `Cookie` header
+    pub fn pair(&self) -> String {
+        format!("{}={}", self.name, self.value)
+    }
+
+    /// The `Cookie` header value which authenticates requests to the ship.
+    /// It is marked sensitive, so that it is not printed by `Debug`.
+    pub fn header_value(&self) -> Result<HeaderValue> {
+        let mut value = HeaderValue::from_str(&self.pair())
+            .map_err(|_| UrbitAPIError::MalformedSessionCookie(self.pair()))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+// Checks that the ship name has the shape of an @p: a single word of three
+// lowercase letters for a galaxy, or words of six lowercase letters
+// separated by `-` (or `--` for comets). The syllables themselves are not
+// checked, so a name such as `abc` which only has the right shape passes.
+fn is_valid_ship_name(ship_name: &str) -> bool {
+    if ship_name.len() == 3 {
+        return ship_name.chars().all(|c| c.is_ascii_lowercase());
+    }
+    let mut words = ship_name.split('-').filter(|word| !word.is_empty());
+    let mut words_seen = 0;
+    let valid = words.all(|word| {
+        words_seen += 1;
+        word.len() == 6 && word.chars().all(|c| c.is_ascii_lowercase())
+    });
+    valid && words_seen > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that the pair and attributes are read from a `Set-Cookie`
+    fn parses_set_cookie() {
+        let now = SystemTime::UNIX_EPOCH;
+        let cookie = SessionCookie::parse(
+            "urbauth-~zod=0v5.abcde; Path=/; Domain=zod.arvo.network; Max-Age=604800",
+            now,
+        )
+        .unwrap();
+        assert_eq!(cookie.pair(), "urbauth-~zod=0v5.abcde");
+        assert_eq!(format!("{:?}", cookie.header_value().unwrap()), "Sensitive");
+        assert_eq!(cookie.ship_name, "zod");
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+        assert_eq!(cookie.domain.as_deref(), Some("zod.arvo.network"));
+        assert_eq!(cookie.expires_at, Some(now + Duration::from_secs(604800)));
+
+        let cookie = SessionCookie::parse(
+            "urbauth-~sampel-palnet=0v5.abcde; Expires=Thu, 01 Jan 1970 00:01:00 GMT",
+            now,
+        )
+        .unwrap();
+        assert_eq!(cookie.ship_name, "sampel-palnet");
+        assert_eq!(cookie.expires_at, Some(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    // Verify that malformed cookies are rejected instead of panicking
+    fn rejects_malformed_cookies() {
+        let now = SystemTime::UNIX_EPOCH;
+        for set_cookie in &[
+            "",
+            "urbauth",
+            "urbauth-~zod",
+            "session=abc",
+            "urbauth-~z0d=abc",
+            "urbauth-~sampel-palne=abc",
+            "urbauth-~Sampel-palnet=abc",
+        ] {
+            match SessionCookie::parse(set_cookie, now) {
+                Err(UrbitAPIError::MalformedSessionCookie(_)) => {}
+                res => panic!("Unexpected parse result: {:?}", res),
+            }
+        }
+    }
+}
@@ -7,12 +7,65 @@ pub type Result<T> = std::result::Result<T, UrbitAPIError>;
 pub enum UrbitAPIError {
     #[error("Failed logging in to the ship given the provided url and code.")]
     FailedToLogin,
+    #[error("The session cookie set by the ship is malformed: {0}")]
+    MalformedSessionCookie(String),
+    #[error("The ship rejected the session auth.")]
+    Unauthorized,
+    #[error("The saved session is invalid.")]
+    InvalidSavedSession,
+    #[error("The saved session has expired.")]
+    SessionExpired,
+    #[error("The access code of the ship is not remembered, so the session cannot be renewed.")]
+    CodeNotRemembered,
+    #[error("Failed to log out of the ship.")]
+    FailedToLogout,
+    #[error("The session has been logged out.")]
+    LoggedOut,
     #[error("Failed to create a new channel.")]
     FailedToCreateNewChannel,
+    #[error("Failed to delete the channel.")]
+    FailedToDeleteChannel,
     #[error("Failed to create a new subscription.")]
     FailedToCreateNewSubscription,
+    #[error("The subscription was nacked by the app: {0}")]
+    SubscriptionNacked(String),
+    #[error("No subscription found for app {0} and path {1}.")]
+    SubscriptionNotFound(String, String),
+    #[error("The facts of the subscription for app {0} and path {1} go to its handler instead of its message list.")]
+    FactsSentToHandler(String, String),
+    #[error("Failed to unsubscribe.")]
+    FailedToUnsubscribe,
+    #[error("Failed to ack event {0}.")]
+    FailedToAck(u64),
+    #[error("Failed to send the batch of {0} channel actions.")]
+    FailedToSendBatch(usize),
+    #[error("Failed to send the poke.")]
+    FailedToPoke,
+    #[error("The poke was nacked by the app: {0}")]
+    PokeNacked(String),
+    #[error("Timed out waiting for the ship to ack message {0}.")]
+    AckTimeout(u64),
+    #[error("Failed to open the event stream of the channel.")]
+    FailedToOpenEventStream,
+    #[error("The event stream of the channel has closed.")]
+    EventStreamClosed,
+    #[error("Nothing arrived on the event stream of the channel within the stale window.")]
+    ConnectionStale,
+    #[error("No such scry path: {0}")]
+    ScryPathNotFound(String),
+    #[error("The scry failed with status code {0}.")]
+    FailedToScry(u16),
+    #[error("The thread failed: {0}")]
+    ThreadFailed(String),
     #[error("{0}")]
     Other(String),
     #[error(transparent)]
     ReqwestError(#[from] ReqError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    JsonError(#[from] json::Error),
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    SerdeError(#[from] serde_json::Error),
 }
@@ -0,0 +1,114 @@
+use crate::subscription::CreationID;
+use json::JsonValue;
+
+/// An event received over the SSE connection of a `Channel`, parsed out of
+/// the json data of the event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelEvent {
+    /// The response to the poke with the given message id. A nack carries
+    /// the error trace of the app.
+    PokeAck {
+        id: u64,
+        result: std::result::Result<(), String>,
+    },
+    /// The response to the subscription with the given `CreationID`. A nack
+    /// carries the error trace of the app.
+    WatchAck {
+        id: CreationID,
+        result: std::result::Result<(), String>,
+    },
+    /// A fact sent to the subscription with the given `CreationID`
+    Fact {
+        id: CreationID,
+        mark: Option<String>,
+        payload: JsonValue,
+    },
+    /// The subscription with the given `CreationID` was kicked by its app
+    Quit { id: CreationID },
+    /// An event which could not be parsed, holding the data of the event
+    Error(String),
+}
+
+impl ChannelEvent {
+    /// Parses the data of an SSE event into a `ChannelEvent`
+    pub fn parse(data: &str) -> ChannelEvent {
+        let json = match json::parse(data) {
+            Ok(json) => json,
+            Err(_) => return ChannelEvent::Error(data.to_string()),
+        };
+        let id = match json["id"].as_u64() {
+            Some(id) => id,
+            None => return ChannelEvent::Error(data.to_string()),
+        };
+        match json["response"].as_str() {
+            Some("poke") => ChannelEvent::PokeAck {
+                id,
+                result: ack_result(&json),
+            },
+            Some("subscribe") => ChannelEvent::WatchAck {
+                id,
+                result: ack_result(&json),
+            },
+            Some("diff") => ChannelEvent::Fact {
+                id,
+                mark: json["mark"].as_str().map(|mark| mark.to_string()),
+                payload: json["json"].clone(),
+            },
+            Some("quit") => ChannelEvent::Quit { id },
+            _ => ChannelEvent::Error(data.to_string()),
+        }
+    }
+}
+
+/// Reads the result out of a poke or subscribe response json. A nack
+/// carries the error trace of the app in its `err` field.
+fn ack_result(json: &JsonValue) -> std::result::Result<(), String> {
+    let err = &json["err"];
+    if err.is_null() {
+        Ok(())
+    } else if let Some(trace) = err.as_str() {
+        Err(trace.to_string())
+    } else {
+        Err(err.dump())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that each kind of eyre response is parsed
+    fn parses_responses() {
+        assert_eq!(
+            ChannelEvent::parse(r#"{"id": 2, "response": "poke", "ok": "ok"}"#),
+            ChannelEvent::PokeAck {
+                id: 2,
+                result: Ok(())
+            }
+        );
+        assert_eq!(
+            ChannelEvent::parse(r#"{"id": 3, "response": "subscribe", "err": "trace"}"#),
+            ChannelEvent::WatchAck {
+                id: 3,
+                result: Err("trace".to_string())
+            }
+        );
+        assert_eq!(
+            ChannelEvent::parse(r#"{"id": 3, "response": "diff", "json": {"a": 1}}"#),
+            ChannelEvent::Fact {
+                id: 3,
+                mark: None,
+                payload: json::object! { "a": 1 },
+            }
+        );
+        assert_eq!(
+            ChannelEvent::parse(r#"{"id": 3, "response": "quit"}"#),
+            ChannelEvent::Quit { id: 3 }
+        );
+        assert_eq!(
+            ChannelEvent::parse("not json"),
+            ChannelEvent::Error("not json".to_string())
+        );
+    }
+}
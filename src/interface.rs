@@ -1,21 +1,115 @@
 use crate::channel::Channel;
+use crate::cookie::SessionCookie;
 use crate::error::{Result, UrbitAPIError};
-use json::JsonValue;
-use reqwest::blocking::{Client, Response};
-use reqwest::header::{HeaderValue, COOKIE};
+use crate::options::ChannelOptions;
+use crate::session::SavedSession;
+use crate::sse::{self, EventClient, ReceiverSource};
+use json::{object, JsonValue};
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderValue, IntoHeaderName, COOKIE};
+use reqwest::{Certificate, Proxy};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
-// The struct which holds the details for connecting to a given Urbit ship
+// The struct which holds the details for connecting to a given Urbit ship.
+// Cloning a `ShipInterface` is cheap, as every clone shares the same
+// `Session`.
 #[derive(Debug, Clone)]
 pub struct ShipInterface {
     /// The URL of the ship given as `http://ip:port` such as
     /// `http://0.0.0.0:8080`.
     pub url: String,
-    /// The session auth string header value
-    pub session_auth: HeaderValue,
     /// The ship name
     pub ship_name: String,
+    /// The session shared with every `Channel` created from this interface
+    session: Arc<Session>,
+}
+
+// The authenticated session with a ship
+struct Session {
+    /// The current auth of the session, which is replaced when logging in
+    /// again
+    auth: RwLock<SessionAuth>,
+    /// The access code of the ship, if it is remembered so that the
+    /// session can be renewed by logging in again
+    ship_code: Mutex<Option<String>>,
     /// The Reqwest `Client` to be reused for making requests
     req_client: Client,
+    /// The Reqwest `Client` used for the long-lived SSE connections of
+    /// channels
+    event_client: EventClient,
+    /// The urls of the channels created with the session which have not
+    /// been deleted, with the state shared with each `Channel`
+    open_channels: Mutex<Vec<(String, ChannelRegistration)>>,
+    /// Whether the session has been ended with `logout`
+    logged_out: AtomicBool,
+}
+
+// The auth of a session with a ship
+#[derive(Clone)]
+struct SessionAuth {
+    /// The session auth cookie
+    cookie: SessionCookie,
+    /// The session auth string header value, holding just the
+    /// `name=value` pair of the cookie
+    session_auth: HeaderValue,
+}
+
+// The state of a channel created with a session which is shared between
+// the `Channel` and the session, so that `logout` can delete the channel
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChannelRegistration {
+    /// The next message id of the channel
+    next_id: Arc<AtomicU64>,
+    /// Whether the channel has been deleted on the ship
+    deleted: Arc<AtomicBool>,
+}
+
+impl ChannelRegistration {
+    /// Records that the channel has sent every message id before `next_id`
+    pub(crate) fn record_next_id(&self, next_id: u64) {
+        self.next_id.fetch_max(next_id, Ordering::SeqCst);
+    }
+
+    /// Takes a fresh message id for the channel
+    fn take_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Records that the channel has been deleted on the ship
+    pub(crate) fn mark_deleted(&self) {
+        self.deleted.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the channel has been deleted on the ship
+    pub(crate) fn is_deleted(&self) -> bool {
+        self.deleted.load(Ordering::SeqCst)
+    }
+}
+
+// The session auth and access code are redacted, so that printing a
+// `ShipInterface` or anything holding one does not leak them
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let remembers_code = self
+            .ship_code
+            .lock()
+            .map(|code| code.is_some())
+            .unwrap_or(false);
+        f.debug_struct("Session")
+            .field("auth", &"<redacted>")
+            .field(
+                "ship_code",
+                &if remembers_code { "<redacted>" } else { "None" },
+            )
+            .field("req_client", &self.req_client)
+            .field("event_client", &self.event_client)
+            .field("open_channels", &self.open_channels)
+            .field("logged_out", &self.logged_out)
+            .finish()
+    }
 }
 
 impl ShipInterface {
@@ -24,83 +118,658 @@ impl ShipInterface {
     /// `http://0.0.0.0:8080`. `ship_code` is the code acquire from your ship
     /// by typing `+code` in dojo.
     pub fn new(ship_url: &str, ship_code: &str) -> Result<ShipInterface> {
-        let client = Client::new();
-        let login_url = format!("{}/~/login", ship_url);
-        let resp = client
-            .post(&login_url)
-            .body("password=".to_string() + &ship_code)
-            .send()?;
+        ShipInterface::builder(ship_url).login(ship_code)
+    }
 
-        // Check for status code
-        if resp.status().as_u16() != 204 {
-            return Err(UrbitAPIError::FailedToLogin);
-        }
+    /// Creates a `ShipInterfaceBuilder`, which configures the HTTP clients
+    /// used to connect to the ship at `ship_url` before logging in.
+    pub fn builder(ship_url: &str) -> ShipInterfaceBuilder {
+        ShipInterfaceBuilder::new(ship_url)
+    }
+
+    /// Creates a `ShipInterface` from the session auth cookie of an
+    /// existing session, without logging in again. `expires_at` is when
+    /// the session expires, if known.
+    pub fn from_session_auth(
+        ship_url: &str,
+        session_auth: &str,
+        expires_at: Option<SystemTime>,
+    ) -> Result<ShipInterface> {
+        ShipInterface::builder(ship_url).from_session_auth(session_auth, expires_at)
+    }
+
+    /// Creates a `ShipInterface` from a `SavedSession`, without logging in
+    /// again.
+    pub fn from_saved_session(saved: &SavedSession) -> Result<ShipInterface> {
+        ShipInterface::builder(&saved.url).from_saved_session(saved)
+    }
+
+    /// Returns a `SavedSession` which can be persisted and later used to
+    /// create a `ShipInterface` without logging in again.
+    pub fn save_session(&self) -> Result<SavedSession> {
+        let session_auth = self.session_auth();
+        let session_auth = session_auth.to_str().map_err(|_| {
+            UrbitAPIError::MalformedSessionCookie(
+                String::from_utf8_lossy(session_auth.as_bytes()).to_string(),
+            )
+        })?;
+        Ok(SavedSession {
+            url: self.url.clone(),
+            session_auth: session_auth.to_string(),
+            expires_at: self.session_expiry(),
+        })
+    }
+
+    /// Returns when the session expires, if known
+    pub fn session_expiry(&self) -> Option<SystemTime> {
+        self.auth().cookie.expires_at
+    }
 
-        // Acquire the session auth header value
-        let session_auth = resp
-            .headers()
-            .get("set-cookie")
-            .ok_or(UrbitAPIError::FailedToLogin)?;
+    /// Returns the session auth cookie, holding the domain, path and
+    /// expiry the ship set on it
+    pub fn session_cookie(&self) -> SessionCookie {
+        self.auth().cookie
+    }
+
+    /// Remembers the access code of the ship. Once remembered, if the ship
+    /// rejects the session because it has expired, the `ShipInterface`
+    /// logs in again and retries the failed request once.
+    pub fn remember_code(&self, ship_code: &str) {
+        *self
+            .session
+            .ship_code
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(ship_code.to_string());
+    }
 
-        // Convert sessions auth to a string
-        let auth_string = session_auth
-            .to_str()
-            .map_err(|_| UrbitAPIError::FailedToLogin)?;
+    /// Whether the access code of the ship is remembered
+    pub fn remembers_code(&self) -> bool {
+        self.session
+            .ship_code
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
 
-        // Trim the auth string to acquire the ship name
-        let ship_name = &auth_string[9..auth_string.find('=').unwrap()];
+    /// Logs into the ship again with the remembered access code. The new
+    /// session auth is shared with every clone of this `ShipInterface` and
+    /// every `Channel` created from it.
+    pub fn relogin(&self) -> Result<()> {
+        if self.is_logged_out() {
+            return Err(UrbitAPIError::LoggedOut);
+        }
+        let ship_code = self
+            .session
+            .ship_code
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let ship_code = ship_code.as_ref().ok_or(UrbitAPIError::CodeNotRemembered)?;
+        let auth = login(&self.session.req_client, &self.url, ship_code)?;
+        *self.session.auth.write().unwrap_or_else(|e| e.into_inner()) = auth;
+        Ok(())
+    }
 
-        Ok(ShipInterface {
+    // Builds a `ShipInterface` out of an authenticated session
+    fn from_parts(
+        ship_url: &str,
+        auth: SessionAuth,
+        req_client: Client,
+        event_client: EventClient,
+    ) -> ShipInterface {
+        ShipInterface {
             url: ship_url.to_string(),
-            session_auth: session_auth.clone(),
-            ship_name: ship_name.to_string(),
-            req_client: client,
-        })
+            ship_name: auth.cookie.ship_name.clone(),
+            session: Arc::new(Session {
+                auth: RwLock::new(auth),
+                ship_code: Mutex::new(None),
+                req_client,
+                event_client,
+                open_channels: Mutex::new(vec![]),
+                logged_out: AtomicBool::new(false),
+            }),
+        }
     }
 
-    /// Create a `Channel` using this `ShipInterface`
-    pub fn create_channel(&mut self) -> Result<Channel> {
-        Channel::new(self)
+    // Returns the current auth of the session
+    fn auth(&self) -> SessionAuth {
+        self.session
+            .auth
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Returns the session auth string header value
+    pub fn session_auth(&self) -> HeaderValue {
+        self.auth().session_auth
+    }
+
+    /// Create a `Channel` using this `ShipInterface`. The `Channel` holds
+    /// its own handle to the session, so any number of channels can be
+    /// created from the same `ShipInterface`.
+    pub fn create_channel(&self) -> Result<Channel> {
+        Channel::new(self.clone())
+    }
+
+    /// Create a `Channel` using this `ShipInterface` with the given
+    /// `ChannelOptions`
+    pub fn create_channel_with_options(&self, options: ChannelOptions) -> Result<Channel> {
+        Channel::with_options(self.clone(), options)
     }
 
     // Send a put request using the `ShipInterface`
     pub fn send_put_request(&self, url: &str, body: &JsonValue) -> Result<Response> {
         let json = body.dump();
-        let resp = self
-            .req_client
-            .put(url)
-            .header(COOKIE, self.session_auth.clone())
-            .header("Content-Type", "application/json")
-            .body(json);
+        self.send_with_auth(|client| {
+            client
+                .put(url)
+                .header("Content-Type", "application/json")
+                .body(json.clone())
+        })
+    }
+
+    // Send a post request using the `ShipInterface`
+    pub fn send_post_request(&self, url: &str, body: &JsonValue) -> Result<Response> {
+        let json = body.dump();
+        self.send_with_auth(|client| {
+            client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(json.clone())
+        })
+    }
+
+    /// Ends the session by deleting every open channel created from this
+    /// `ShipInterface` and logging out of the ship. Afterwards every
+    /// request made with this `ShipInterface`, its clones, or its channels
+    /// fails with `UrbitAPIError::LoggedOut`. The logout is attempted even
+    /// if deleting a channel fails, and the first error is returned. Until
+    /// the logout succeeds the session stays usable, so `logout` can be
+    /// retried. Live `Channel` handles become inert once their channel has
+    /// been deleted: the ship no longer knows the channel, and it is not
+    /// deleted again when the `Channel` is dropped.
+    pub fn logout(&self) -> Result<()> {
+        if self.is_logged_out() {
+            return Err(UrbitAPIError::LoggedOut);
+        }
+        let mut first_err = None;
 
-        Ok(resp.send()?)
+        // Delete the open channels, so that the ship drops their event
+        // streams
+        let channels = self
+            .session
+            .open_channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for (channel_url, registration) in channels {
+            let mut body = json::parse(r#"[]"#).unwrap();
+            body[0] = object! {
+                "id": registration.take_id(),
+                "action": "delete",
+            };
+            match self.send_put_request(&channel_url, &body) {
+                Ok(resp) if resp.status().as_u16() == 204 => {
+                    registration.mark_deleted();
+                    self.unregister_channel(&channel_url);
+                }
+                Ok(_) => {
+                    first_err.get_or_insert(UrbitAPIError::FailedToDeleteChannel);
+                }
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        let logout_url = format!("{}/~/logout", self.url);
+        match self.send_with_auth(|client| client.post(&logout_url)) {
+            Ok(resp) if resp.status().is_success() => {
+                self.session.logged_out.store(true, Ordering::SeqCst);
+                *self
+                    .session
+                    .ship_code
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner()) = None;
+            }
+            Ok(_) => {
+                first_err.get_or_insert(UrbitAPIError::FailedToLogout);
+            }
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the session has been ended with `logout`
+    pub fn is_logged_out(&self) -> bool {
+        self.session.logged_out.load(Ordering::SeqCst)
+    }
+
+    // Records a channel created with the session, whose next message id is
+    // `next_id`, so that `logout` deletes it. Returns the state shared with
+    // the `Channel`.
+    pub(crate) fn register_channel(&self, channel_url: &str, next_id: u64) -> ChannelRegistration {
+        let registration = ChannelRegistration::default();
+        registration.record_next_id(next_id);
+        self.session
+            .open_channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((channel_url.to_string(), registration.clone()));
+        registration
+    }
+
+    // Forgets a channel which has been deleted
+    pub(crate) fn unregister_channel(&self, channel_url: &str) {
+        self.session
+            .open_channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(url, _)| url != channel_url);
+    }
+
+    // Send the request built by `request` with the session auth. If the
+    // ship rejects the session and the access code is remembered, logs in
+    // again and retries the request once.
+    fn send_with_auth(&self, request: impl Fn(&Client) -> RequestBuilder) -> Result<Response> {
+        if self.is_logged_out() {
+            return Err(UrbitAPIError::LoggedOut);
+        }
+        let client = &self.session.req_client;
+        let resp = request(client).header(COOKIE, self.session_auth()).send()?;
+        if !is_auth_failure(&resp) || !self.remembers_code() {
+            return Ok(resp);
+        }
+
+        self.relogin()?;
+        Ok(request(client).header(COOKIE, self.session_auth()).send()?)
+    }
+
+    /// Runs the thread `thread_name` from the `base` desk on the ship,
+    /// passing `body` as its input with `input_mark`, and returns the
+    /// output of the thread converted with `output_mark`. If the thread
+    /// fails, its error trace is returned as `UrbitAPIError::ThreadFailed`.
+    pub fn run_thread(
+        &self,
+        input_mark: &str,
+        thread_name: &str,
+        output_mark: &str,
+        body: &JsonValue,
+    ) -> Result<JsonValue> {
+        self.run_thread_on_desk("base", input_mark, thread_name, output_mark, body)
+    }
+
+    /// Runs the thread `thread_name` from the given desk on the ship,
+    /// passing `body` as its input with `input_mark`, and returns the
+    /// output of the thread converted with `output_mark`.
+    pub fn run_thread_on_desk(
+        &self,
+        desk: &str,
+        input_mark: &str,
+        thread_name: &str,
+        output_mark: &str,
+        body: &JsonValue,
+    ) -> Result<JsonValue> {
+        let thread_url = format!(
+            "{}/spider/{}/{}/{}/{}",
+            self.url, desk, input_mark, thread_name, output_mark
+        );
+        let resp = self.send_post_request(&thread_url, body)?;
+        if is_auth_failure(&resp) {
+            return Err(UrbitAPIError::Unauthorized);
+        }
+        let status = resp.status().as_u16();
+        let text = resp.text()?;
+        match status {
+            200 => Ok(json::parse(&text)?),
+            _ => Err(UrbitAPIError::ThreadFailed(text)),
+        }
+    }
+
+    /// Scries the given app/path on the ship with the provided mark, which
+    /// should convert to json such as `json`, and returns the parsed json.
+    pub fn scry(&self, app: &str, path: &str, mark: &str) -> Result<JsonValue> {
+        let resp = self.send_scry_request(app, path, mark)?;
+        Ok(json::parse(&resp.text()?)?)
+    }
+
+    /// Scries the given app/path on the ship with the provided mark and
+    /// returns the raw bytes of the result.
+    pub fn scry_bytes(&self, app: &str, path: &str, mark: &str) -> Result<Vec<u8>> {
+        let resp = self.send_scry_request(app, path, mark)?;
+        Ok(resp.bytes()?.to_vec())
+    }
+
+    // Send a scry request using the `ShipInterface`, checking the status
+    // code of the response
+    fn send_scry_request(&self, app: &str, path: &str, mark: &str) -> Result<Response> {
+        let scry_url = format!("{}/~/scry/{}{}.{}", self.url, app, path, mark);
+        let resp = self.send_with_auth(|client| client.get(&scry_url))?;
+        // A rejected session may be redirected to the login page, which
+        // answers with a 200
+        if is_auth_failure(&resp) {
+            return Err(UrbitAPIError::Unauthorized);
+        }
+
+        match resp.status().as_u16() {
+            200 => Ok(resp),
+            404 => Err(UrbitAPIError::ScryPathNotFound(format!("{}{}", app, path))),
+            status => Err(UrbitAPIError::FailedToScry(status)),
+        }
+    }
+
+    // Open the SSE connection of a channel using the `ShipInterface`. If a
+    // `last_event_id` is provided, the ship resends every event after it.
+    pub(crate) fn open_event_stream(
+        &self,
+        url: &str,
+        last_event_id: Option<u64>,
+    ) -> ReceiverSource {
+        if self.is_logged_out() {
+            return sse::closed(UrbitAPIError::LoggedOut);
+        }
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, self.session_auth());
+        if let Some(id) = last_event_id {
+            headers.append("last-event-id", HeaderValue::from(id));
+        }
+        sse::open(&self.session.event_client, url, headers)
+    }
+}
+
+/// Configures the HTTP clients of a `ShipInterface` before logging in. The
+/// settings apply both to requests and to the SSE connections of channels.
+#[derive(Debug, Clone)]
+pub struct ShipInterfaceBuilder {
+    /// The URL of the ship given as `http://ip:port`
+    url: String,
+    /// The timeout for connecting to the ship
+    connect_timeout: Option<Duration>,
+    /// The timeout for a request, from sending it to reading its body
+    timeout: Option<Duration>,
+    /// The longest an SSE connection may go without receiving any data
+    sse_timeout: Option<Duration>,
+    /// Extra root certificates to trust, such as self-signed certificates
+    root_certificates: Vec<Certificate>,
+    /// Whether invalid TLS certificates are accepted
+    accept_invalid_certs: bool,
+    /// The proxy every connection is routed through
+    proxy: Option<Proxy>,
+    /// The headers sent with every request
+    default_headers: HeaderMap,
+}
+
+impl ShipInterfaceBuilder {
+    /// Creates a `ShipInterfaceBuilder` for the ship at `ship_url`, with a
+    /// 30 second request timeout and no SSE timeout.
+    pub fn new(ship_url: &str) -> ShipInterfaceBuilder {
+        ShipInterfaceBuilder {
+            url: ship_url.to_string(),
+            connect_timeout: None,
+            timeout: Some(Duration::from_secs(30)),
+            sse_timeout: None,
+            root_certificates: vec![],
+            accept_invalid_certs: false,
+            proxy: None,
+            default_headers: HeaderMap::new(),
+        }
+    }
+
+    /// Sets the timeout for connecting to the ship
+    pub fn connect_timeout(mut self, timeout: Duration) -> ShipInterfaceBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for a request, from sending it to reading its body.
+    /// `None` disables the timeout.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> ShipInterfaceBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the longest the SSE connection of a channel may go without
+    /// receiving any data before it is dropped and reconnected. `None`, the
+    /// default, disables the timeout. Opening the SSE connection is bounded
+    /// by this timeout, or by the request timeout if it is disabled.
+    pub fn sse_timeout(mut self, timeout: Option<Duration>) -> ShipInterfaceBuilder {
+        self.sse_timeout = timeout;
+        self
+    }
+
+    /// Trusts an extra root certificate, such as the self-signed
+    /// certificate of a hosted ship
+    pub fn add_root_certificate(mut self, cert: Certificate) -> ShipInterfaceBuilder {
+        self.root_certificates.push(cert);
+        self
+    }
+
+    /// Accepts invalid TLS certificates. This is only meant for dev ships,
+    /// as it leaves the connection open to impersonation.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> ShipInterfaceBuilder {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Routes every connection to the ship through `proxy`
+    pub fn proxy(mut self, proxy: Proxy) -> ShipInterfaceBuilder {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Adds a header which is sent with every request, such as a custom
+    /// `User-Agent`
+    pub fn default_header<K: IntoHeaderName>(
+        mut self,
+        name: K,
+        value: HeaderValue,
+    ) -> ShipInterfaceBuilder {
+        self.default_headers.insert(name, value);
+        self
+    }
+
+    /// Logs into the ship with the access code and creates the
+    /// `ShipInterface`
+    pub fn login(self, ship_code: &str) -> Result<ShipInterface> {
+        let (req_client, event_client) = self.build_clients()?;
+        let auth = login(&req_client, &self.url, ship_code)?;
+        Ok(ShipInterface::from_parts(
+            &self.url,
+            auth,
+            req_client,
+            event_client,
+        ))
+    }
+
+    /// Creates the `ShipInterface` from the session auth cookie of an
+    /// existing session, without logging in again. `expires_at` is when
+    /// the session expires, if known.
+    pub fn from_session_auth(
+        self,
+        session_auth: &str,
+        expires_at: Option<SystemTime>,
+    ) -> Result<ShipInterface> {
+        let mut cookie = SessionCookie::parse(session_auth, SystemTime::now())
+            .map_err(|_| UrbitAPIError::InvalidSavedSession)?;
+        if expires_at.is_some() {
+            cookie.expires_at = expires_at;
+        }
+        let auth = SessionAuth {
+            session_auth: cookie.header_value()?,
+            cookie,
+        };
+
+        let (req_client, event_client) = self.build_clients()?;
+        Ok(ShipInterface::from_parts(
+            &self.url,
+            auth,
+            req_client,
+            event_client,
+        ))
+    }
+
+    /// Creates the `ShipInterface` from a `SavedSession`, without logging
+    /// in again.
+    pub fn from_saved_session(self, saved: &SavedSession) -> Result<ShipInterface> {
+        if saved.is_expired() {
+            return Err(UrbitAPIError::SessionExpired);
+        }
+        self.from_session_auth(&saved.session_auth, saved.expires_at)
+    }
+
+    // Builds the `Client` for requests and the `EventClient` for SSE
+    // connections. Opening an SSE connection is bounded by the
+    // `sse_timeout`, or else by the request timeout.
+    fn build_clients(&self) -> Result<(Client, EventClient)> {
+        let req_client = self.client_builder().timeout(self.timeout).build()?;
+        let event_client = EventClient::new(
+            self.client_builder(),
+            self.sse_timeout.or(self.timeout),
+            self.sse_timeout,
+        )?;
+        Ok((req_client, event_client))
+    }
+
+    // Creates a `ClientBuilder` with the settings shared by both clients
+    fn client_builder(&self) -> ClientBuilder {
+        let mut builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .default_headers(self.default_headers.clone());
+        for cert in &self.root_certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        builder
+    }
+}
+
+// Logs into the ship with the access code, returning the auth of the new
+// session
+fn login(client: &Client, ship_url: &str, ship_code: &str) -> Result<SessionAuth> {
+    let login_url = format!("{}/~/login", ship_url);
+    let resp = client
+        .post(&login_url)
+        .body(format!("password={}", ship_code))
+        .send()?;
+
+    // Check for status code
+    if resp.status().as_u16() != 204 {
+        return Err(UrbitAPIError::FailedToLogin);
+    }
+
+    // Parse the session auth cookie out of the response
+    let cookie = SessionCookie::from_headers(resp.headers(), SystemTime::now())?;
+    Ok(SessionAuth {
+        session_auth: cookie.header_value()?,
+        cookie,
+    })
+}
+
+// Whether the ship rejected the session of a request, either with a 401 or
+// 403, or by redirecting to the login page
+pub(crate) fn is_auth_failure(resp: &Response) -> bool {
+    match resp.status().as_u16() {
+        401 | 403 => true,
+        _ => resp.url().path().starts_with("/~/login"),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::subscription::Subscription;
+    use crate::test_support::{fake_server, respond, NO_CONTENT};
+
+    // The ways a ship rejects the session of a request
+    const REJECTIONS: [&str; 3] = [
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 303 See Other\r\nLocation: /~/login?redirect=/\r\nContent-Length: 0\r\n\r\n",
+    ];
+
+    // Starts a fake ship which answers every request without the session
+    // `0v5.fresh` with `rejection`, and records whether each request it
+    // receives is a login or another request. A login only hands out the session
+    // `0v5.fresh` if `renews` is set.
+    fn rejecting_ship(
+        rejection: &'static str,
+        renews: bool,
+    ) -> (String, Arc<Mutex<Vec<&'static str>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let url = fake_server(move |request, stream| {
+            if request.is("GET", "/~/login") {
+                let page = "<html>login</html>";
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
+                    page.len(),
+                    page
+                );
+                return respond(stream, &resp);
+            }
+            if request.is("POST", "/~/login") {
+                recorded.lock().unwrap().push("login");
+                let session = if renews { "0v5.fresh" } else { "0v5.stale" };
+                let resp = format!(
+                    "HTTP/1.1 204 No Content\r\nSet-Cookie: urbauth-~zod={}; Path=/\r\n\r\n",
+                    session
+                );
+                return respond(stream, &resp);
+            }
+            recorded.lock().unwrap().push("request");
+            if request.header("cookie") == Some("urbauth-~zod=0v5.fresh") {
+                respond(stream, NO_CONTENT)
+            } else {
+                respond(stream, rejection)
+            }
+        });
+        (url, requests)
+    }
+
     #[test]
     // Verify that we can login to a local `~zod` dev ship.
     fn can_login() {
-        let ship_interface =
+        let _ship_interface =
             ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
     }
 
     #[test]
     // Verify that we can create a channel
     fn can_create_channel() {
-        let mut ship_interface =
+        let ship_interface =
             ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
         let channel = ship_interface.create_channel().unwrap();
-        channel.delete_channel();
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that channels opened in the same second do not collide
+    fn can_create_concurrent_channels() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let options = ChannelOptions::default().uid_prefix("test");
+        let first = ship_interface
+            .create_channel_with_options(options.clone())
+            .unwrap();
+        let second = ship_interface.create_channel_with_options(options).unwrap();
+        assert!(first.uid.starts_with("test-"));
+        assert_ne!(first.uid, second.uid);
+        first.delete_channel().unwrap();
+        second.delete_channel().unwrap();
     }
 
     #[test]
     // Verify that we can create a channel
     fn can_subscribe() {
-        let mut ship_interface =
+        let ship_interface =
             ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
         let mut channel = ship_interface.create_channel().unwrap();
         channel
@@ -108,20 +777,286 @@ mod tests {
             .unwrap();
 
         channel.find_subscription("chat-view", "/primary");
-        channel.unsubscribe("chat-view", "/primary");
-        channel.delete_channel();
+        channel.unsubscribe("chat-view", "/primary").unwrap();
+        assert!(channel.find_subscription("chat-view", "/primary").is_none());
+        channel.delete_channel().unwrap();
     }
 
     #[test]
     // Verify that we can make a poke
     fn can_poke() {
-        let mut ship_interface =
+        let ship_interface =
             ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
         let mut channel = ship_interface.create_channel().unwrap();
         let poke_res = channel
             .poke("hood", "helm-hi", "A poke has been made")
             .unwrap();
         assert!(poke_res.status().as_u16() == 204);
-        channel.delete_channel();
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that a poke is acked by the app
+    fn can_poke_and_wait() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let mut channel = ship_interface.create_channel().unwrap();
+        channel
+            .poke_and_wait(
+                "hood",
+                "helm-hi",
+                "A poke has been acked",
+                std::time::Duration::from_secs(10),
+            )
+            .unwrap();
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that we can scry
+    fn can_scry() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        ship_interface.scry("graph-store", "/keys", "json").unwrap();
+        match ship_interface.scry("graph-store", "/no-such-path", "json") {
+            Err(UrbitAPIError::ScryPathNotFound(_)) => {}
+            res => panic!("Unexpected scry result: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that scries report missing paths, other failures and rejected
+    // sessions apart, including sessions redirected to the login page
+    fn reports_scry_failures() {
+        let url = fake_server(|request, stream| {
+            let resp = if request.is("GET", "/~/scry/app/ok.json") {
+                "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n{\"a\":1}"
+            } else if request.is("GET", "/~/scry/app/missing.json") {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+            } else {
+                "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+            };
+            respond(stream, resp)
+        });
+        let ship_interface =
+            ShipInterface::from_session_auth(&url, "urbauth-~zod=0v5.abcde", None).unwrap();
+        let scried = ship_interface.scry("app", "/ok", "json").unwrap();
+        assert_eq!(scried, object! { "a": 1 });
+        match ship_interface.scry("app", "/missing", "json") {
+            Err(UrbitAPIError::ScryPathNotFound(path)) => assert_eq!(path, "app/missing"),
+            res => panic!("Unexpected scry result: {:?}", res),
+        }
+        match ship_interface.scry_bytes("app", "/broken", "json") {
+            Err(UrbitAPIError::FailedToScry(500)) => {}
+            res => panic!("Unexpected scry result: {:?}", res),
+        }
+
+        for rejection in REJECTIONS.iter() {
+            let (url, _) = rejecting_ship(rejection, false);
+            let ship_interface =
+                ShipInterface::from_session_auth(&url, "urbauth-~zod=0v5.stale", None).unwrap();
+            match ship_interface.scry("app", "/ok", "json") {
+                Err(UrbitAPIError::Unauthorized) => {}
+                res => panic!("Unexpected scry result: {:?}", res),
+            }
+            match ship_interface.run_thread("json", "thread", "json", &JsonValue::Null) {
+                Err(UrbitAPIError::Unauthorized) => {}
+                res => panic!("Unexpected thread result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    // Verify that a failing thread returns its error
+    fn can_run_failing_thread() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        match ship_interface.run_thread("json", "no-such-thread", "json", &JsonValue::Null) {
+            Err(UrbitAPIError::ThreadFailed(_)) => {}
+            res => panic!("Unexpected thread result: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that we can login with a configured `ShipInterfaceBuilder`
+    fn can_login_with_builder() {
+        let ship_interface = ShipInterface::builder("http://0.0.0.0:8080")
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Some(Duration::from_secs(10)))
+            .default_header(
+                reqwest::header::USER_AGENT,
+                HeaderValue::from_static("urbit-http-api-test"),
+            )
+            .login("lidlut-tabwed-pillex-ridrup")
+            .unwrap();
+        let channel = ship_interface.create_channel().unwrap();
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that queued actions are sent in a single batch
+    fn can_flush_batch() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let mut channel = ship_interface.create_channel().unwrap();
+        channel
+            .queue_poke("hood", "helm-hi", "A batched poke")
+            .unwrap();
+        channel.queue_subscription("chat-view", "/primary").unwrap();
+        assert_eq!(channel.queued_actions(), 2);
+        channel.flush().unwrap();
+        assert_eq!(channel.queued_actions(), 0);
+        assert!(channel.find_subscription("chat-view", "/primary").is_some());
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that a dropped channel is deleted when `delete_on_drop` is set
+    fn can_delete_channel_on_drop() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let mut channel = ship_interface.create_channel().unwrap();
+        channel.delete_on_drop = true;
+        channel
+            .create_new_subscription("chat-view", "/primary")
+            .unwrap();
+        let channel_url = channel.url.clone();
+        std::mem::drop(channel);
+        assert!(ship_interface
+            .session
+            .open_channels
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(url, _)| url != &channel_url));
+    }
+
+    #[test]
+    // Verify that the pump thread calls the poke ack handler
+    fn can_pump_poke_acks() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let mut channel = ship_interface.create_channel().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        channel.on_poke_ack(move |id, result| {
+            let _ = sender.send((id, result.clone()));
+        });
+        let pump = channel.start_pump();
+        let id = pump.with_channel(|channel| {
+            channel.poke("hood", "helm-hi", "A pumped poke").unwrap();
+            channel.message_id_count - 1
+        });
+        let acked = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        let acked = std::iter::once(acked)
+            .chain(receiver.try_iter())
+            .find(|(acked_id, _)| *acked_id == id);
+        assert_eq!(acked, Some((id, Ok(()))));
+        pump.shutdown().delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that waiting for a message times out on a quiet subscription
+    fn can_wait_for_message() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let mut channel = ship_interface.create_channel().unwrap();
+        channel
+            .create_new_subscription("chat-view", "/primary")
+            .unwrap();
+        let message = channel
+            .next_message("chat-view", "/primary", Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(message, None);
+        match channel.next_message("chat-view", "/no-such-path", Duration::from_millis(500)) {
+            Err(UrbitAPIError::SubscriptionNotFound(_, _)) => {}
+            res => panic!("Unexpected message result: {:?}", res),
+        }
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that logging out ends the session
+    fn can_logout() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let _channel = ship_interface.create_channel().unwrap();
+        ship_interface.logout().unwrap();
+        match ship_interface.scry("graph-store", "/keys", "json") {
+            Err(UrbitAPIError::LoggedOut) => {}
+            res => panic!("Unexpected scry result: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that a saved session can be reused
+    fn can_reuse_saved_session() {
+        let ship_interface =
+            ShipInterface::new("http://0.0.0.0:8080", "lidlut-tabwed-pillex-ridrup").unwrap();
+        let saved = ship_interface.save_session().unwrap();
+        let ship_interface = ShipInterface::from_saved_session(&saved).unwrap();
+        let channel = ship_interface.create_channel().unwrap();
+        channel.delete_channel().unwrap();
+    }
+
+    #[test]
+    // Verify that the session auth and access code are not printed
+    fn redacts_session_in_debug() {
+        let ship_interface =
+            ShipInterface::from_session_auth("http://127.0.0.1:1", "urbauth-~zod=0v5.abcde", None)
+                .unwrap();
+        ship_interface.remember_code("lidlut-tabwed-pillex-ridrup");
+        let debug = format!("{:?}", ship_interface);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("0v5.abcde"));
+        assert!(!debug.contains("lidlut-tabwed-pillex-ridrup"));
+    }
+
+    #[test]
+    // Verify that a request rejected for its session, with a 401, a 403 or
+    // a redirect to the login page, logs in again with the remembered code
+    // and is retried exactly once
+    fn relogins_and_retries_once() {
+        for rejection in REJECTIONS.iter() {
+            let (url, requests) = rejecting_ship(rejection, true);
+            let ship_interface =
+                ShipInterface::from_session_auth(&url, "urbauth-~zod=0v5.stale", None).unwrap();
+            let channel_url = format!("{}/~/channel/test", url);
+            // Without a remembered code the rejection is returned as is
+            let resp = ship_interface
+                .send_put_request(&channel_url, &object! {})
+                .unwrap();
+            assert!(is_auth_failure(&resp));
+            ship_interface.remember_code("code");
+            let resp = ship_interface
+                .send_put_request(&channel_url, &object! {})
+                .unwrap();
+            assert_eq!(resp.status().as_u16(), 204);
+            assert_eq!(
+                *requests.lock().unwrap(),
+                vec!["request", "request", "login", "request"]
+            );
+
+            // A renewed session which is rejected as well is not renewed
+            // again
+            let (url, requests) = rejecting_ship(rejection, false);
+            let ship_interface =
+                ShipInterface::from_session_auth(&url, "urbauth-~zod=0v5.stale", None).unwrap();
+            ship_interface.remember_code("code");
+            let resp = ship_interface
+                .send_put_request(&format!("{}/~/channel/test", url), &object! {})
+                .unwrap();
+            assert!(is_auth_failure(&resp));
+            assert_eq!(
+                *requests.lock().unwrap(),
+                vec!["request", "login", "request"]
+            );
+        }
+    }
+
+    #[test]
+    // Verify that channels can be moved to and shared between threads
+    fn channel_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Channel>();
     }
 }
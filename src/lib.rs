@@ -1,9 +1,40 @@
+pub mod ack;
+pub mod batch;
 pub mod channel;
+pub mod cookie;
 pub mod error;
+pub mod event;
 pub mod interface;
+pub mod options;
+pub mod pump;
+pub mod retry;
+pub mod session;
+pub(crate) mod sse;
 pub mod subscription;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "serde")]
+pub mod typed;
 
-pub use channel::Channel;
+#[cfg(feature = "async")]
+pub mod async_channel;
+#[cfg(feature = "async")]
+pub mod async_interface;
+
+pub use ack::AckPolicy;
+pub use batch::BatchPolicy;
+pub use channel::{Channel, ConnectionState, ConnectionStatus, QuitOutcome};
+pub use cookie::SessionCookie;
 pub use error::{Result, UrbitAPIError};
-pub use interface::ShipInterface;
-pub use subscription::Subscription;
+pub use event::ChannelEvent;
+pub use interface::{ShipInterface, ShipInterfaceBuilder};
+pub use options::{ChannelOptions, OpenAction};
+pub use pump::ChannelPump;
+pub use retry::RetryPolicy;
+pub use session::SavedSession;
+pub use subscription::{Subscription, SubscriptionMessage};
+
+#[cfg(feature = "async")]
+pub use async_channel::AsyncChannel;
+#[cfg(feature = "async")]
+pub use async_interface::AsyncShipInterface;
@@ -0,0 +1,225 @@
+use crate::channel::ship_without_sig;
+use crate::subscription::Subscription;
+use json::{object, JsonValue};
+use rand::Rng;
+use std::time::SystemTime;
+
+/// The action sent in the first PUT to a channel, which creates it on the
+/// ship. It is sent with message id 1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpenAction {
+    /// Poke `hood` with `helm-hi`, which prints the text in the dojo of
+    /// the ship. This is how channels were always opened.
+    HelmHi(String),
+    /// Poke an app, on the ship of the channel if `ship` is `None`
+    Poke {
+        ship: Option<String>,
+        app: String,
+        mark: String,
+        json: JsonValue,
+    },
+    /// Subscribe to an app/path, on the ship of the channel if `ship` is
+    /// `None`. Once the app acks it, the `Subscription` is added to the
+    /// `subscription_list` of the channel.
+    Subscribe {
+        ship: Option<String>,
+        app: String,
+        path: String,
+    },
+    /// Ack event 0, which creates the channel without any side effects on
+    /// the ship
+    NoOp,
+}
+
+impl Default for OpenAction {
+    fn default() -> OpenAction {
+        OpenAction::HelmHi("Opening channel".to_string())
+    }
+}
+
+impl OpenAction {
+    /// The json of the action, where `ship_name` is the ship of the
+    /// channel
+    pub fn to_json(&self, ship_name: &str) -> JsonValue {
+        let target = |ship: &Option<String>| ship_without_sig(ship.as_deref().unwrap_or(ship_name));
+        match self {
+            OpenAction::HelmHi(text) => object! {
+                "id": 1,
+                "action": "poke",
+                "ship": ship_without_sig(ship_name),
+                "app": "hood",
+                "mark": "helm-hi",
+                "json": text.clone(),
+            },
+            OpenAction::Poke {
+                ship,
+                app,
+                mark,
+                json,
+            } => object! {
+                "id": 1,
+                "action": "poke",
+                "ship": target(ship),
+                "app": app.clone(),
+                "mark": mark.clone(),
+                "json": json.clone(),
+            },
+            OpenAction::Subscribe { ship, app, path } => object! {
+                "id": 1,
+                "action": "subscribe",
+                "ship": target(ship),
+                "app": app.clone(),
+                "path": path.clone(),
+            },
+            OpenAction::NoOp => object! {
+                "id": 1,
+                "action": "ack",
+                "event-id": 0,
+            },
+        }
+    }
+
+    /// The `Subscription` created by the action, if it is a subscribe
+    pub fn subscription(&self, channel_uid: &str, ship_name: &str) -> Option<Subscription> {
+        match self {
+            OpenAction::Subscribe { ship, app, path } => Some(Subscription {
+                channel_uid: channel_uid.to_string(),
+                creation_id: 1,
+                ship: ship_without_sig(ship.as_deref().unwrap_or(ship_name)),
+                app: app.clone(),
+                path: path.clone(),
+                message_list: vec![],
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Options for creating a new channel
+#[derive(Debug, Clone, Default)]
+pub struct ChannelOptions {
+    /// A prefix for the uid of the channel, so that the service which owns
+    /// a channel can be identified in the channel list of eyre
+    pub uid_prefix: Option<String>,
+    /// The action which opens the channel
+    pub open_action: OpenAction,
+}
+
+impl ChannelOptions {
+    /// Sets the prefix for the uid of the channel
+    pub fn uid_prefix(mut self, prefix: &str) -> ChannelOptions {
+        self.uid_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Sets the action which opens the channel
+    pub fn open_action(mut self, action: OpenAction) -> ChannelOptions {
+        self.open_action = action;
+        self
+    }
+
+    /// Generates a new channel uid following these options
+    pub fn new_uid(&self) -> String {
+        channel_uid(self.uid_prefix.as_deref())
+    }
+}
+
+/// Generates a channel uid out of the current UNIX time and six random hex
+/// digits, like the official JS client, so that channels opened in the
+/// same second do not collide. Characters of the prefix which are not
+/// allowed in a url path segment are replaced with `-`.
+pub fn channel_uid(prefix: Option<&str>) -> String {
+    let mut rng = rand::thread_rng();
+    let secs = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => rng.gen::<u32>() as u64,
+    };
+    let uid = format!("{}-{:06x}", secs, rng.gen::<u32>() & 0xff_ffff);
+    match prefix {
+        Some(prefix) => {
+            let prefix: String = prefix
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                    _ => '-',
+                })
+                .collect();
+            format!("{}-{}", prefix, uid)
+        }
+        None => uid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that uids are prefixed and do not collide within a second
+    fn generates_prefixed_uids() {
+        let options = ChannelOptions::default().uid_prefix("my service");
+        let uid = options.new_uid();
+        assert!(uid.starts_with("my-service-"));
+        assert_ne!(options.new_uid(), uid);
+    }
+
+    #[test]
+    // Verify that the default open action is the `helm-hi` poke
+    fn opens_with_helm_hi_by_default() {
+        let json = ChannelOptions::default().open_action.to_json("~zod");
+        assert_eq!(json["app"], "hood");
+        assert_eq!(json["ship"], "zod");
+        assert_eq!(json["json"], "Opening channel");
+    }
+
+    #[test]
+    // Verify the json of every open action, which is always sent with
+    // message id 1
+    fn builds_open_action_json() {
+        let poke = OpenAction::Poke {
+            ship: Some("~bus".to_string()),
+            app: "app".to_string(),
+            mark: "mark".to_string(),
+            json: object! { "a": 1 },
+        };
+        assert_eq!(
+            poke.to_json("~zod"),
+            object! {
+                "id": 1,
+                "action": "poke",
+                "ship": "bus",
+                "app": "app",
+                "mark": "mark",
+                "json": object! { "a": 1 },
+            }
+        );
+
+        let subscribe = OpenAction::Subscribe {
+            ship: None,
+            app: "app".to_string(),
+            path: "/path".to_string(),
+        };
+        assert_eq!(
+            subscribe.to_json("~zod"),
+            object! {
+                "id": 1,
+                "action": "subscribe",
+                "ship": "zod",
+                "app": "app",
+                "path": "/path",
+            }
+        );
+        let sub = subscribe.subscription("uid", "~zod").unwrap();
+        assert_eq!((sub.creation_id, sub.ship.as_str()), (1, "zod"));
+
+        assert_eq!(
+            OpenAction::NoOp.to_json("~zod"),
+            object! {
+                "id": 1,
+                "action": "ack",
+                "event-id": 0,
+            }
+        );
+        assert!(OpenAction::NoOp.subscription("uid", "~zod").is_none());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::channel::Channel;
+use crate::error::UrbitAPIError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How long the pump thread waits for an event before releasing the
+// `Channel`, so that other threads can use it
+const PUMP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A callback which is called on the pump thread with every error hit
+/// while pumping the events of a `Channel`
+pub type PumpErrorHandler = Box<dyn FnMut(&UrbitAPIError) + Send>;
+
+/// A background thread which owns a `Channel`, reads its events as they
+/// arrive, and calls its fact, poke ack and quit handlers. The `Channel` is
+/// still usable through `with_channel` while the pump runs. The handlers of
+/// the `Channel` run on the pump thread while it holds the `Channel`, so
+/// calling `with_channel` from one of them deadlocks.
+pub struct ChannelPump {
+    /// The `Channel` shared with the pump thread
+    channel: Arc<Mutex<Channel>>,
+    /// Whether the pump thread should keep running
+    running: Arc<AtomicBool>,
+    /// The number of threads waiting in `with_channel`, which the pump
+    /// thread lets go first, and the `Condvar` which wakes the pump thread
+    /// once they are done waiting
+    waiting: Arc<(Mutex<usize>, Condvar)>,
+    /// Called with every error hit by the pump thread
+    error_handler: Arc<Mutex<Option<PumpErrorHandler>>>,
+    /// The pump thread, until it has been joined
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ChannelPump {
+    /// Moves the `Channel` to a new pump thread
+    pub fn start(channel: Channel) -> ChannelPump {
+        let channel = Arc::new(Mutex::new(channel));
+        let running = Arc::new(AtomicBool::new(true));
+        let waiting = Arc::new((Mutex::new(0), Condvar::new()));
+        let error_handler: Arc<Mutex<Option<PumpErrorHandler>>> = Arc::new(Mutex::new(None));
+        let thread = {
+            let channel = channel.clone();
+            let running = running.clone();
+            let waiting = waiting.clone();
+            let error_handler = error_handler.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    // Let the threads waiting in `with_channel` go first
+                    let (count, released) = &*waiting;
+                    let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+                    while *count > 0 {
+                        count = released.wait(count).unwrap_or_else(|e| e.into_inner());
+                    }
+                    drop(count);
+
+                    let pumped = channel
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .pump_events(PUMP_INTERVAL);
+                    if let Err(e) = pumped {
+                        if let Some(handler) =
+                            &mut *error_handler.lock().unwrap_or_else(|e| e.into_inner())
+                        {
+                            handler(&e);
+                        }
+                        // The pump stops once the connection has closed for
+                        // good
+                        if let UrbitAPIError::EventStreamClosed = e {
+                            running.store(false, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+        };
+        ChannelPump {
+            channel,
+            running,
+            waiting,
+            error_handler,
+            thread: Some(thread),
+        }
+    }
+
+    /// Calls `f` with the `Channel`, waiting for the pump thread to release
+    /// it, such as to poke or subscribe while the pump runs. Calling it
+    /// from a handler of the `Channel` deadlocks, as handlers run while the
+    /// pump thread holds the `Channel`.
+    pub fn with_channel<T>(&self, f: impl FnOnce(&mut Channel) -> T) -> T {
+        let (count, released) = &*self.waiting;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        let mut channel = self.channel.lock().unwrap_or_else(|e| e.into_inner());
+        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        released.notify_all();
+        f(&mut channel)
+    }
+
+    /// Sets the callback which is called on the pump thread with every
+    /// error hit while pumping events, such as failed acks, flushes or
+    /// resubscriptions. It is called without holding the `Channel`.
+    pub fn on_error(&self, handler: impl FnMut(&UrbitAPIError) + Send + 'static) {
+        *self.error_handler.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+    }
+
+    /// Whether the pump thread is still running. It stops on `shutdown`,
+    /// or once the SSE connection has closed and will not be reopened.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Stops the pump thread, waits for it to exit, and returns the
+    /// `Channel`
+    pub fn shutdown(mut self) -> Channel {
+        self.stop();
+        let channel = self.channel.clone();
+        std::mem::drop(self);
+        match Arc::try_unwrap(channel) {
+            Ok(channel) => channel.into_inner().unwrap_or_else(|e| e.into_inner()),
+            Err(_) => unreachable!("the pump thread has exited"),
+        }
+    }
+
+    /// Stops the pump thread and waits for it to exit
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ChannelPump {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+/// How often, and how quickly, a failed operation is retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of retries, or `None` to retry forever
+    pub max_retries: Option<u32>,
+    /// The delay before the first retry
+    pub initial_delay: Duration,
+    /// The delay doubles after every failed retry, up to `max_delay`
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A `RetryPolicy` which never retries
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: Some(0),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Whether another retry should be made after `retries` retries have
+    /// already failed.
+    pub fn should_retry(&self, retries: u32) -> bool {
+        match self.max_retries {
+            Some(max) => retries < max,
+            None => true,
+        }
+    }
+
+    /// The delay to wait before the retry which follows `retries` failed
+    /// retries.
+    pub fn delay(&self, retries: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retries);
+        match self.initial_delay.checked_mul(factor) {
+            Some(delay) => std::cmp::min(delay, self.max_delay),
+            None => self.max_delay,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: Some(5),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that the delay doubles up to `max_delay`, even when doubling
+    // overflows
+    fn doubles_delay_up_to_max() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(4), Duration::from_secs(16));
+        assert_eq!(policy.delay(5), Duration::from_secs(30));
+        assert_eq!(policy.delay(64), Duration::from_secs(30));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(30));
+
+        let policy = RetryPolicy {
+            max_retries: None,
+            initial_delay: Duration::from_secs(u64::MAX / 2),
+            max_delay: Duration::from_secs(u64::MAX),
+        };
+        assert_eq!(policy.delay(2), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    // Verify that retries stop once `max_retries` have failed
+    fn stops_after_max_retries() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(0));
+        assert!(policy.should_retry(4));
+        assert!(!policy.should_retry(5));
+        assert!(!RetryPolicy::never().should_retry(0));
+
+        let policy = RetryPolicy {
+            max_retries: None,
+            ..RetryPolicy::default()
+        };
+        assert!(policy.should_retry(u32::MAX));
+    }
+}
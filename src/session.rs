@@ -0,0 +1,121 @@
+use crate::error::{Result, UrbitAPIError};
+use json::object;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// A session with a ship which has been saved, so that a `ShipInterface`
+/// can be created from it later without logging in again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedSession {
+    /// The URL of the ship
+    pub url: String,
+    /// The session auth cookie
+    pub session_auth: String,
+    /// When the session expires, if known
+    pub expires_at: Option<SystemTime>,
+}
+
+impl SavedSession {
+    /// Whether the session has expired
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= SystemTime::now(),
+            None => false,
+        }
+    }
+
+    /// Serializes the `SavedSession` into a json string
+    pub fn to_json(&self) -> String {
+        let expires_at = self
+            .expires_at
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        let json = object! {
+            "url": self.url.clone(),
+            "session_auth": self.session_auth.clone(),
+            "expires_at": expires_at,
+        };
+        json.dump()
+    }
+
+    /// Deserializes a `SavedSession` from a json string created by
+    /// `to_json`
+    pub fn from_json(json_str: &str) -> Result<SavedSession> {
+        let json = json::parse(json_str)?;
+        let url = json["url"]
+            .as_str()
+            .ok_or(UrbitAPIError::InvalidSavedSession)?;
+        let session_auth = json["session_auth"]
+            .as_str()
+            .ok_or(UrbitAPIError::InvalidSavedSession)?;
+        let expires_at = json["expires_at"]
+            .as_u64()
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        Ok(SavedSession {
+            url: url.to_string(),
+            session_auth: session_auth.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Writes the `SavedSession` to a file. As the file holds a live
+    /// session cookie, on unix it is only readable and writable by its
+    /// owner.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path)?;
+        // The mode only applies to new files, so restrict existing ones too
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(self.to_json().as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads a `SavedSession` from a file written by `save_to_file`
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<SavedSession> {
+        SavedSession::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Verify that a `SavedSession` survives a round trip through json
+    fn saved_session_round_trip() {
+        let saved = SavedSession {
+            url: "http://0.0.0.0:8080".to_string(),
+            session_auth: "urbauth-~zod=0v5.abcde".to_string(),
+            expires_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+        };
+        assert_eq!(SavedSession::from_json(&saved.to_json()).unwrap(), saved);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // Verify that a saved session file is only accessible by its owner
+    fn saves_file_privately() {
+        let saved = SavedSession {
+            url: "http://0.0.0.0:8080".to_string(),
+            session_auth: "urbauth-~zod=0v5.abcde".to_string(),
+            expires_at: None,
+        };
+        let path = std::env::temp_dir().join(format!("saved-session-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        saved.save_to_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let loaded = SavedSession::load_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(loaded, saved);
+    }
+}
@@ -0,0 +1,413 @@
+// A minimal implementation of the `text/event-stream` format which eyre
+// uses to send channel events.
+use crate::error::Result;
+use crate::error::UrbitAPIError;
+use crate::interface::is_auth_failure;
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the thread reading an SSE connection waits for data before
+/// checking whether its `ReceiverSource` has been dropped
+const READ_WAIT: Duration = Duration::from_millis(500);
+
+/// The timeout for opening an SSE connection when every timeout is
+/// disabled. Some timeout has to be set on the request, as the
+/// `READ_WAIT` of the client would apply otherwise.
+const UNBOUNDED_OPEN: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A message sent by the thread which reads an SSE connection
+#[derive(Debug)]
+pub enum SseMessage {
+    /// The connection has been opened
+    Opened,
+    /// An event has been received
+    Event(Event),
+    /// Data which held no event, such as a heartbeat comment, has been
+    /// received
+    Heartbeat,
+    /// The connection has failed or been closed. No more messages will be
+    /// sent after this one.
+    Closed(UrbitAPIError),
+}
+
+/// The receiving end of an SSE connection which is read on its own thread.
+/// Dropping it stops the thread and closes the connection.
+#[derive(Debug)]
+pub struct ReceiverSource {
+    receiver: Receiver<SseMessage>,
+    /// Set once the `ReceiverSource` is dropped, so that the thread reading
+    /// the connection stops
+    dropped: Arc<AtomicBool>,
+}
+
+impl ReceiverSource {
+    /// Waits up to `timeout` for the next message from the connection
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> std::result::Result<SseMessage, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+impl Drop for ReceiverSource {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Creates a `ReceiverSource` which receives the messages sent with the
+/// returned `Sender`, together with the flag which is set once it is
+/// dropped
+pub fn channel() -> (Sender<SseMessage>, Arc<AtomicBool>, ReceiverSource) {
+    let (sender, receiver) = mpsc::channel();
+    let dropped = Arc::new(AtomicBool::new(false));
+    let source = ReceiverSource {
+        receiver,
+        dropped: dropped.clone(),
+    };
+    (sender, dropped, source)
+}
+
+/// The `Client` for the SSE connections of channels, together with the
+/// timeouts which the thread reading a connection applies
+#[derive(Debug, Clone)]
+pub struct EventClient {
+    client: Client,
+    /// The timeout for opening a connection, until its headers arrive
+    open_timeout: Duration,
+    /// The longest a connection may go without receiving any data
+    idle_timeout: Option<Duration>,
+}
+
+impl EventClient {
+    /// Builds the `EventClient` out of `builder`. Its reads wait at most
+    /// `READ_WAIT`, so that a connection which has stopped sending data is
+    /// still closed once its `ReceiverSource` is dropped.
+    pub fn new(
+        builder: ClientBuilder,
+        open_timeout: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Result<EventClient> {
+        Ok(EventClient {
+            client: builder.timeout(READ_WAIT).build()?,
+            open_timeout: open_timeout.unwrap_or(UNBOUNDED_OPEN),
+            idle_timeout,
+        })
+    }
+}
+
+/// A single Server-Sent Event received from the ship
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The id of the event, used when acking it
+    pub id: Option<String>,
+    /// The type of the event, if one was provided
+    pub event_type: Option<String>,
+    /// The data of the event. Multiple `data` lines are joined with `\n`.
+    pub data: String,
+}
+
+/// Incrementally parses raw bytes from an SSE connection into `Event`s
+#[derive(Debug, Default)]
+pub struct EventParser {
+    /// Bytes which have been received but not yet ended by a newline
+    buffer: Vec<u8>,
+    id: Option<String>,
+    event_type: Option<String>,
+    data: Vec<String>,
+}
+
+impl EventParser {
+    /// Create a new `EventParser`
+    pub fn new() -> EventParser {
+        EventParser::default()
+    }
+
+    /// Feeds a chunk of bytes into the parser and returns every `Event`
+    /// which was completed by it.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Event> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = vec![];
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line_bytes: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line_bytes);
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+            if let Some(event) = self.parse_line(line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Parses a single line, returning an `Event` if the line ended one.
+    fn parse_line(&mut self, line: &str) -> Option<Event> {
+        // An empty line dispatches the event
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // Lines starting with a colon are comments
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.find(':') {
+            Some(i) => {
+                let value = &line[i + 1..];
+                (&line[..i], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line, ""),
+        };
+        match field {
+            "id" => self.id = Some(value.to_string()),
+            "event" => self.event_type = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    /// Builds an `Event` out of the fields read so far and resets them.
+    fn dispatch(&mut self) -> Option<Event> {
+        let id = self.id.take();
+        let event_type = self.event_type.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = self.data.join("\n");
+        self.data.clear();
+        Some(Event {
+            id,
+            event_type,
+            data,
+        })
+    }
+}
+
+/// Opens an SSE connection to `url` on a new thread, which sends
+/// everything read from the connection to the returned `ReceiverSource`.
+/// The thread exits, closing the connection, once the connection closes or
+/// within `READ_WAIT` of the `ReceiverSource` being dropped.
+pub fn open(client: &EventClient, url: &str, headers: HeaderMap) -> ReceiverSource {
+    let (sender, dropped, receiver) = channel();
+    let client = client.clone();
+    let url = url.to_string();
+    thread::spawn(move || {
+        let err = read_events(&client, &url, headers, &sender, &dropped);
+        let _ = sender.send(SseMessage::Closed(err));
+    });
+    receiver
+}
+
+/// Returns a `ReceiverSource` for a connection which could not be opened,
+/// which only holds the `Closed` message with the error.
+pub fn closed(err: UrbitAPIError) -> ReceiverSource {
+    let (sender, _, receiver) = channel();
+    let _ = sender.send(SseMessage::Closed(err));
+    receiver
+}
+
+/// Reads events from the SSE connection until it fails, returning the error
+/// which ended it.
+fn read_events(
+    client: &EventClient,
+    url: &str,
+    headers: HeaderMap,
+    sender: &Sender<SseMessage>,
+    dropped: &AtomicBool,
+) -> UrbitAPIError {
+    let resp = client
+        .client
+        .get(url)
+        .headers(headers)
+        .header(ACCEPT, "text/event-stream")
+        .timeout(client.open_timeout)
+        .send();
+    let mut resp = match resp {
+        Ok(resp) => resp,
+        Err(e) => return e.into(),
+    };
+    if is_auth_failure(&resp) {
+        return UrbitAPIError::Unauthorized;
+    }
+    if resp.status().as_u16() >= 300 {
+        return UrbitAPIError::FailedToOpenEventStream;
+    }
+    // A rejected session may also be answered with the html of the login
+    // page, which is not an event stream
+    let is_event_stream = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if !is_event_stream {
+        return UrbitAPIError::Unauthorized;
+    }
+    if sender.send(SseMessage::Opened).is_err() {
+        return UrbitAPIError::EventStreamClosed;
+    }
+
+    let mut parser = EventParser::new();
+    let mut buf = [0; 4096];
+    let mut last_data = Instant::now();
+    loop {
+        if dropped.load(Ordering::SeqCst) {
+            return UrbitAPIError::EventStreamClosed;
+        }
+        let n = match resp.read(&mut buf) {
+            Ok(0) => return UrbitAPIError::EventStreamClosed,
+            Ok(n) => n,
+            // Nothing arrived within `READ_WAIT`, which only ends the
+            // connection once the `idle_timeout` has passed
+            Err(e) if is_timeout(&e) => match client.idle_timeout {
+                Some(idle) if last_data.elapsed() >= idle => return e.into(),
+                _ => continue,
+            },
+            Err(e) => return e.into(),
+        };
+        last_data = Instant::now();
+        let events = parser.feed(&buf[..n]);
+        // Report data without events, so that the connection is known to
+        // be alive
+        if events.is_empty() && sender.send(SseMessage::Heartbeat).is_err() {
+            return UrbitAPIError::EventStreamClosed;
+        }
+        for event in events {
+            if sender.send(SseMessage::Event(event)).is_err() {
+                return UrbitAPIError::EventStreamClosed;
+            }
+        }
+    }
+}
+
+/// Whether a read from a connection failed because it timed out
+fn is_timeout(e: &io::Error) -> bool {
+    e.get_ref()
+        .and_then(|e| e.downcast_ref::<reqwest::Error>())
+        .is_some_and(|e| e.is_timeout())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_server, respond, EVENT_STREAM};
+
+    #[test]
+    // Verify that events split across chunks are parsed correctly
+    fn parses_split_events() {
+        let mut parser = EventParser::new();
+        assert!(parser.feed(b"id: 3\ndata: {\"id\":").is_empty());
+        let events = parser.feed(b" 2}\n\n:heartbeat\n\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, Some("3".to_string()));
+        assert_eq!(events[0].data, "{\"id\": 2}");
+    }
+
+    #[test]
+    // Verify that multiple data lines are joined
+    fn joins_data_lines() {
+        let mut parser = EventParser::new();
+        let events = parser.feed(b"data: a\r\ndata: b\r\n\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "a\nb");
+    }
+
+    #[test]
+    // Verify that comments, unknown fields and blocks without data do not
+    // produce events
+    fn skips_comments_and_empty_events() {
+        let mut parser = EventParser::new();
+        let events =
+            parser.feed(b": heartbeat\n\nid: 1\n\nretry: 10\nevent: message\ndata\nid: 2\n\n");
+        assert_eq!(
+            events,
+            vec![Event {
+                id: Some("2".to_string()),
+                event_type: Some("message".to_string()),
+                data: "".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    // Verify that a login page served instead of the event stream is
+    // reported as a rejected session
+    fn rejects_login_page() {
+        let url = fake_server(|_, stream| {
+            let body = "<html>login</html>";
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            respond(stream, &resp)
+        });
+        let url = format!("{}/~/channel/test", url);
+
+        let client = EventClient::new(Client::builder(), None, None).unwrap();
+        let receiver = open(&client, &url, HeaderMap::new());
+        match receiver.recv_timeout(std::time::Duration::from_secs(10)) {
+            Ok(SseMessage::Closed(UrbitAPIError::Unauthorized)) => {}
+            res => panic!("Unexpected SSE message: {:?}", res),
+        }
+    }
+
+    #[test]
+    // Verify that a connection which stays quiet for longer than
+    // `READ_WAIT` keeps being read, and that it is closed once its
+    // `ReceiverSource` is dropped
+    fn reads_quiet_connection_until_dropped() {
+        let (closed_sender, closed) = mpsc::channel();
+        let closed_sender = std::sync::Mutex::new(closed_sender);
+        let url = fake_server(move |_, stream| {
+            respond(stream, EVENT_STREAM);
+            thread::sleep(READ_WAIT * 3);
+            respond(stream, "id: 1\ndata: late\n\n");
+            let _ = stream.read(&mut [0; 1]);
+            let _ = closed_sender.lock().unwrap().send(());
+            false
+        });
+
+        let client = EventClient::new(Client::builder(), None, None).unwrap();
+        let receiver = open(&client, &url, HeaderMap::new());
+        let timeout = Duration::from_secs(10);
+        assert!(matches!(
+            receiver.recv_timeout(timeout),
+            Ok(SseMessage::Opened)
+        ));
+        match receiver.recv_timeout(timeout) {
+            Ok(SseMessage::Event(event)) => assert_eq!(event.data, "late"),
+            res => panic!("Unexpected SSE message: {:?}", res),
+        }
+        drop(receiver);
+        assert!(closed.recv_timeout(timeout).is_ok());
+    }
+
+    #[test]
+    // Verify that a connection which stays quiet for longer than the
+    // `idle_timeout` is closed
+    fn closes_idle_connection() {
+        let url = fake_server(|_, stream| {
+            respond(stream, EVENT_STREAM);
+            let _ = stream.read(&mut [0; 1]);
+            false
+        });
+
+        let client = EventClient::new(Client::builder(), None, Some(READ_WAIT)).unwrap();
+        let receiver = open(&client, &url, HeaderMap::new());
+        let timeout = Duration::from_secs(10);
+        assert!(matches!(
+            receiver.recv_timeout(timeout),
+            Ok(SseMessage::Opened)
+        ));
+        match receiver.recv_timeout(timeout) {
+            Ok(SseMessage::Closed(UrbitAPIError::IoError(_))) => {}
+            res => panic!("Unexpected SSE message: {:?}", res),
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use eventsource_threaded::event::Event;
-use json;
+#[cfg(feature = "serde")]
+use crate::error::Result;
+#[cfg(feature = "serde")]
+use crate::typed;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
 
 // ID of the message that created a `Subscription`
 pub type CreationID = u64;
@@ -11,6 +15,8 @@ pub struct Subscription {
     pub channel_uid: String,
     /// The id of the message that created this subscription
     pub creation_id: CreationID,
+    /// The ship of the app being subscribed to, without the `~`
+    pub ship: String,
     /// The app that is being subscribed to
     pub app: String,
     /// The path of the app being subscribed to
@@ -19,34 +25,35 @@ pub struct Subscription {
     pub message_list: Vec<String>,
 }
 
-impl Subscription {
-    /// Verifies if the id of the message id in the event matches thea
-    /// `Subscription` `creation_id`.
-    fn event_matches(&self, event: &Event) -> bool {
-        if let Some(json) = &json::parse(&event.data).ok() {
-            return self.creation_id.to_string() == json["id"].dump();
-        }
-        false
-    }
+/// A message which was received for one of the `Subscription`s of a
+/// channel
+#[derive(Debug, Clone)]
+pub struct SubscriptionMessage {
+    /// The id of the message that created the `Subscription`
+    pub creation_id: CreationID,
+    /// The ship of the `Subscription`
+    pub ship: String,
+    /// The app of the `Subscription`
+    pub app: String,
+    /// The path of the `Subscription`
+    pub path: String,
+    /// The json of the message
+    pub message: String,
+}
 
-    /// Parses an event and adds it to the message list if it's id
-    /// matches the `Subscription` `creation_id`. On success returns
-    /// the length of the message list.
-    pub fn add_to_message_list(&mut self, event: &Event) -> Option<u64> {
-        if self.event_matches(&event) {
-            let json = &json::parse(&event.data).ok()?["json"];
-            if !json.is_null() {
-                self.message_list.push(json.dump());
-                return Some(self.message_list.len() as u64);
-            }
-        }
-        None
+impl SubscriptionMessage {
+    /// Deserializes the json of the message
+    #[cfg(feature = "serde")]
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T> {
+        typed::from_message(&self.message)
     }
+}
 
+impl Subscription {
     /// Pops a message from the front of `Subscription`'s `message_list`.
     /// If no messages are left, returns `None`.
     pub fn pop_message(&mut self) -> Option<String> {
-        if self.message_list.len() == 0 {
+        if self.message_list.is_empty() {
             return None;
         }
         let messages = self.message_list.clone();
@@ -54,4 +61,75 @@ impl Subscription {
         self.message_list = tail.to_vec();
         Some(head.to_owned()[0].clone())
     }
+
+    /// Pops a message from the front of `Subscription`'s `message_list`
+    /// together with the details of the `Subscription`. If no messages
+    /// are left, returns `None`.
+    pub fn pop_subscription_message(&mut self) -> Option<SubscriptionMessage> {
+        let message = self.pop_message()?;
+        Some(SubscriptionMessage {
+            creation_id: self.creation_id,
+            ship: self.ship.clone(),
+            app: self.app.clone(),
+            path: self.path.clone(),
+            message,
+        })
+    }
+
+    /// Pops a message from the front of `Subscription`'s `message_list`
+    /// and deserializes it. If the message fails to decode, the error is
+    /// returned in its place. If no messages are left, returns `None`.
+    #[cfg(feature = "serde")]
+    pub fn pop_typed_message<T: DeserializeOwned>(&mut self) -> Option<Result<T>> {
+        self.pop_message()
+            .map(|message| typed::from_message(&message))
+    }
+
+    /// An iterator which pops and deserializes every message in the
+    /// `message_list`, yielding the decode error for any message that
+    /// fails to decode.
+    #[cfg(feature = "serde")]
+    pub fn typed_messages<T: DeserializeOwned>(&mut self) -> impl Iterator<Item = Result<T>> + '_ {
+        std::iter::from_fn(move || self.pop_typed_message())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::error::UrbitAPIError;
+
+    // A `Subscription` holding the given messages
+    fn subscription(messages: &[&str]) -> Subscription {
+        Subscription {
+            channel_uid: "test".to_string(),
+            creation_id: 1,
+            ship: "zod".to_string(),
+            app: "app".to_string(),
+            path: "/path".to_string(),
+            message_list: messages.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    // Verify that a message which fails to decode is returned as an error
+    // without stopping the messages after it from decoding
+    fn decodes_typed_messages() {
+        let mut sub = subscription(&["1", "\"malformed\"", "3"]);
+        assert_eq!(sub.pop_typed_message::<u32>().unwrap().unwrap(), 1);
+        assert!(matches!(
+            sub.pop_typed_message::<u32>(),
+            Some(Err(UrbitAPIError::SerdeError(_)))
+        ));
+        assert_eq!(sub.pop_typed_message::<u32>().unwrap().unwrap(), 3);
+        assert!(sub.pop_typed_message::<u32>().is_none());
+
+        let mut sub = subscription(&["[1, 2]", "{}", "[3]"]);
+        let decoded: Vec<Result<Vec<u32>>> = sub.typed_messages().collect();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].as_ref().unwrap(), &vec![1, 2]);
+        assert!(decoded[1].is_err());
+        assert_eq!(decoded[2].as_ref().unwrap(), &vec![3]);
+        assert!(sub.message_list.is_empty());
+    }
 }
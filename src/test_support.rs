@@ -0,0 +1,115 @@
+use json::JsonValue;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+// The response of a fake ship with no body
+pub(crate) const NO_CONTENT: &str = "HTTP/1.1 204 No Content\r\n\r\n";
+
+// The response of a fake ship which opens an SSE connection, after which
+// the events are written
+pub(crate) const EVENT_STREAM: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n";
+
+// A request received by a fake ship
+pub(crate) struct Request {
+    // The request line, such as `PUT /~/channel/test HTTP/1.1`
+    pub(crate) line: String,
+    // The headers of the request, with lowercased names
+    pub(crate) headers: Vec<(String, String)>,
+    // The body of the request
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    // Whether this is a request with the given method to a path starting
+    // with `path`
+    pub(crate) fn is(&self, method: &str, path: &str) -> bool {
+        let mut parts = self.line.split_whitespace();
+        parts.next() == Some(method) && parts.next().is_some_and(|p| p.starts_with(path))
+    }
+
+    // The value of the header with the given lowercased name
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The json body of the request, if it has one
+    pub(crate) fn json(&self) -> Option<JsonValue> {
+        json::parse(&String::from_utf8_lossy(&self.body)).ok()
+    }
+}
+
+// Starts a fake ship which hands every request it receives to `answer`
+// together with the connection it arrived on, and returns its url.
+// `answer` writes the response, and returns whether more requests are
+// read from the connection, which is closed otherwise.
+pub(crate) fn fake_server<F>(answer: F) -> String
+where
+    F: Fn(&Request, &mut TcpStream) -> bool + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let answer = Arc::new(answer);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let answer = answer.clone();
+            thread::spawn(move || serve_requests(stream, &*answer));
+        }
+    });
+    url
+}
+
+// Answers the requests sent over a single connection to a fake ship
+fn serve_requests<F>(stream: TcpStream, answer: &F)
+where
+    F: Fn(&Request, &mut TcpStream) -> bool,
+{
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader) {
+        if !answer(&request, reader.get_mut()) {
+            return;
+        }
+    }
+}
+
+// Reads a single request, or `None` once the connection has closed
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return None;
+    }
+    let mut headers = vec![];
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap_or(0) == 0 {
+            return None;
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let mut request = Request {
+        line,
+        headers,
+        body: vec![],
+    };
+    let content_length = request
+        .header("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
+
+// Writes `response` to the connection, returning whether it was written
+pub(crate) fn respond(stream: &mut TcpStream, response: &str) -> bool {
+    stream.write_all(response.as_bytes()).is_ok()
+}
@@ -0,0 +1,17 @@
+// Conversions between serde types and the json which is exchanged with the
+// ship, enabled by the `serde` feature.
+use crate::error::Result;
+use json::JsonValue;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serializes `data` into a `JsonValue`, so that it can be sent to the ship
+/// as structured json.
+pub fn to_json_value<T: Serialize>(data: &T) -> Result<JsonValue> {
+    Ok(json::parse(&serde_json::to_string(data)?)?)
+}
+
+/// Deserializes a json message which was received from the ship
+pub fn from_message<T: DeserializeOwned>(message: &str) -> Result<T> {
+    Ok(serde_json::from_str(message)?)
+}